@@ -16,5 +16,42 @@ export interface AudioFileMetadata {
     genre: string | null,
     release_year: string | null,
     artwork_url: string | null,
+    lyrics_url: string | null,
     duration: number,
+    track_gain: number | null,
+    track_peak: number | null,
+    album_gain: number | null,
+    album_peak: number | null,
+    header_gain: number | null,
+    loudness: number | null,
+    album_loudness: number | null,
+}
+
+export interface LyricsLine {
+    start: number | null,
+    text: string,
+}
+
+export interface Lyrics {
+    source: "lrc" | "txt" | "sylt" | "uslt" | "tag",
+    language: string | null,
+    description: string | null,
+    synced: boolean,
+    lines: LyricsLine[],
+}
+
+export interface Bookmark {
+    id: string,
+    position: number,
+    comment: string | null,
+    created: number,
+    changed: number,
+}
+
+export interface PlayQueue {
+    entries: string[],
+    current: string | null,
+    position: number,
+    changed: number,
+    changed_by: string | null,
 }
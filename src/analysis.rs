@@ -0,0 +1,297 @@
+use crate::AppState;
+use crate::ebur128::{Measurement, Meter, integrated, merge};
+use crate::library::Library;
+use crate::transcode::{Position, Source, TranscodeError};
+use actix_web::web;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const LOUDNESS_FILE: &str = "loudness.json";
+const STORE_VERSION: u32 = 1;
+// ReplayGain 2.0 reference level in LUFS.
+const REFERENCE_LOUDNESS: f64 = -18.0;
+// Results are written to disk after this many tracks and when the job ends.
+const SAVE_INTERVAL: usize = 20;
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct StoreFile {
+    version: u32,
+    tracks: HashMap<String, Measurement>,
+}
+
+// Loudness values computed from the decoded audio, album values are only
+// known when every track of the album has been analyzed.
+#[derive(Clone, Copy, Default)]
+pub struct AnalyzedLoudness {
+    pub loudness: Option<f64>,
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_loudness: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+impl AnalyzedLoudness {
+    // Changes when the track or the rest of its album has been measured,
+    // so responses showing these values can be told apart.
+    pub fn etag(&self) -> String {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        for value in [
+            self.loudness,
+            self.track_gain,
+            self.track_peak,
+            self.album_loudness,
+            self.album_gain,
+            self.album_peak,
+        ] {
+            value.map(f64::to_bits).hash(&mut hasher);
+        }
+        format!("{:x}", hasher.finish())
+    }
+}
+
+// Measurements keyed by md5 ID, kept in the state directory
+// and never written into the audio files themselves.
+pub struct LoudnessStore {
+    state_dir: std::path::PathBuf,
+    tracks: Mutex<HashMap<String, Measurement>>,
+    // Files that could not be decoded are not retried until a restart.
+    failed: Mutex<std::collections::HashSet<String>>,
+    running: AtomicBool,
+    requested: AtomicBool,
+}
+
+impl LoudnessStore {
+    pub fn load(state_dir: &std::path::Path) -> LoudnessStore {
+        let tracks = std::fs::read(state_dir.join(LOUDNESS_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice::<StoreFile>(&data).ok())
+            .filter(|store| store.version == STORE_VERSION)
+            .map(|store| store.tracks)
+            .unwrap_or_default();
+        LoudnessStore {
+            state_dir: state_dir.to_owned(),
+            tracks: Mutex::new(tracks),
+            failed: Mutex::new(Default::default()),
+            running: AtomicBool::new(false),
+            requested: AtomicBool::new(false),
+        }
+    }
+
+    pub fn get(&self, library: &Library, hash: &str) -> AnalyzedLoudness {
+        let Ok(tracks) = self.tracks.lock() else {
+            return AnalyzedLoudness::default();
+        };
+        let Some(track) = tracks.get(hash) else {
+            return AnalyzedLoudness::default();
+        };
+        let mut analyzed = AnalyzedLoudness {
+            loudness: track.integrated,
+            track_gain: track
+                .integrated
+                .map(|loudness| REFERENCE_LOUDNESS - loudness),
+            track_peak: Some(track.true_peak),
+            ..Default::default()
+        };
+
+        let album: Option<Vec<&Measurement>> = album_tracks(library, hash)
+            .iter()
+            .map(|hash| tracks.get(hash))
+            .collect();
+        if let Some(album) = album {
+            analyzed.album_loudness =
+                integrated(&merge(album.iter().map(|track| track.histogram.as_slice())));
+            analyzed.album_gain = analyzed
+                .album_loudness
+                .map(|loudness| REFERENCE_LOUDNESS - loudness);
+            analyzed.album_peak = album.iter().map(|track| track.true_peak).reduce(f64::max);
+        }
+        analyzed
+    }
+
+    fn insert(&self, hash: String, measurement: Measurement) {
+        if let Ok(mut tracks) = self.tracks.lock() {
+            tracks.insert(hash, measurement);
+        }
+    }
+
+    // Drops the measurements of files that left the library.
+    fn retain(&self, library: &Library) {
+        if let Ok(mut tracks) = self.tracks.lock() {
+            tracks.retain(|hash, _| library.audiofiles.contains_key(hash));
+        }
+    }
+
+    // Moves the measurements to the new IDs after the ID mode changed.
+    pub fn migrate(&self, mapping: &HashMap<String, String>) -> std::io::Result<()> {
+        {
+            let mut tracks = self
+                .tracks
+                .lock()
+                .map_err(|_| std::io::Error::other("Poisoned loudness store"))?;
+            let moved: Vec<(String, Measurement)> = tracks
+                .extract_if(|hash, _| mapping.contains_key(hash))
+                .collect();
+            if moved.is_empty() {
+                return Ok(());
+            }
+            for (hash, measurement) in moved {
+                tracks.insert(mapping[&hash].clone(), measurement);
+            }
+        }
+        self.save()
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let data = {
+            let tracks = self
+                .tracks
+                .lock()
+                .map_err(|_| std::io::Error::other("Poisoned loudness store"))?;
+            serde_json::to_vec(&StoreFile {
+                version: STORE_VERSION,
+                tracks: tracks.clone(),
+            })?
+        };
+        std::fs::create_dir_all(&self.state_dir)?;
+        let tmp_path = self.state_dir.join(format!(".{LOUDNESS_FILE}.tmp"));
+        let mut tmp = std::fs::File::create(&tmp_path)?;
+        tmp.write_all(&data)?;
+        tmp.sync_all()?;
+        std::fs::rename(tmp_path, self.state_dir.join(LOUDNESS_FILE))
+    }
+
+    fn contains(&self, hash: &str) -> bool {
+        self.tracks
+            .lock()
+            .is_ok_and(|tracks| tracks.contains_key(hash))
+            || self.failed.lock().is_ok_and(|failed| failed.contains(hash))
+    }
+}
+
+// Tagged albums are grouped like the Subsonic API does, untagged files by directory.
+fn album_tracks(library: &Library, hash: &str) -> Vec<String> {
+    let tagged = library
+        .tracks
+        .get(hash)
+        .is_some_and(|tags| tags.album.is_some());
+    if tagged
+        && let Some(album) = library
+            .track_albums
+            .get(hash)
+            .and_then(|id| library.albums.get(id))
+    {
+        return album.songs.clone();
+    }
+    library
+        .file_parents
+        .get(hash)
+        .and_then(|id| library.directories.get(id))
+        .map(|dir| {
+            dir.files
+                .iter()
+                .filter(|file| {
+                    library
+                        .tracks
+                        .get(*file)
+                        .is_none_or(|tags| tags.album.is_none())
+                })
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_else(|| vec![hash.to_owned()])
+}
+
+pub fn measure(path: &std::path::Path) -> Result<Measurement, TranscodeError> {
+    let mut source = Source::open(path, Position::Start)?;
+    let mut meter: Option<(Meter, u32, usize)> = None;
+    while let Some((samples, sample_rate, channels)) = source.next_block()? {
+        let (meter, rate, count) =
+            meter.get_or_insert_with(|| (Meter::new(sample_rate, channels), sample_rate, channels));
+        if *rate == sample_rate && *count == channels {
+            meter.add(&samples);
+        }
+    }
+    meter
+        .map(|(meter, _, _)| meter.finish())
+        .ok_or(TranscodeError::NoAudioTrack)
+}
+
+// Analyzes tracks that lack ReplayGain tags in a background thread.
+// Requests made while it runs make it look at the library once more.
+pub fn start_analysis(data: web::Data<AppState>) {
+    if !data.settings.loudness.analyze {
+        return;
+    }
+    data.loudness.requested.store(true, Ordering::Release);
+    if data
+        .loudness
+        .running
+        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return;
+    }
+
+    std::thread::spawn(move || {
+        loop {
+            while data.loudness.requested.swap(false, Ordering::AcqRel) {
+                if let Ok(library) = data.library() {
+                    analyze(&data, &library);
+                }
+            }
+            data.loudness.running.store(false, Ordering::Release);
+            // A request arriving between the last pass and clearing `running`
+            // returned early, so it is picked up here.
+            if !data.loudness.requested.load(Ordering::Acquire)
+                || data
+                    .loudness
+                    .running
+                    .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+            {
+                break;
+            }
+        }
+    });
+}
+
+fn analyze(data: &AppState, library: &Library) {
+    let store = &data.loudness;
+    let mut pending: Vec<(&String, &std::path::PathBuf)> = library
+        .audiofiles
+        .iter()
+        .filter(|(hash, _)| {
+            let tagged = library.tracks.get(*hash).is_some_and(|tags| {
+                tags.replay_gain.track_gain.is_some() && tags.replay_gain.album_gain.is_some()
+            });
+            !tagged && !store.contains(hash)
+        })
+        .collect();
+    pending.sort_by_key(|(_, path)| *path);
+
+    store.retain(library);
+    for (index, (hash, path)) in pending.iter().enumerate() {
+        match measure(path) {
+            Ok(measurement) => store.insert(hash.to_string(), measurement),
+            Err(err) => {
+                eprintln!("Failed to analyze the loudness of {path:?}: {err:?}");
+                if let Ok(mut failed) = store.failed.lock() {
+                    failed.insert(hash.to_string());
+                }
+            }
+        }
+        if (index + 1) % SAVE_INTERVAL == 0
+            && let Err(err) = store.save()
+        {
+            eprintln!("Failed to save the loudness store: {err:?}");
+        }
+    }
+    if let Err(err) = store.save() {
+        eprintln!("Failed to save the loudness store: {err:?}");
+    }
+}
@@ -0,0 +1,172 @@
+use lofty::{file::TaggedFileExt, picture::PictureType};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Distinguishes the temporary files of thumbnails written at the same time.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub struct Artwork {
+    pub data: Vec<u8>,
+    pub mime: String,
+}
+
+#[derive(Debug)]
+pub enum ArtworkError {
+    NotFound,
+    ImageError(image::ImageError),
+    IOError(std::io::Error),
+}
+
+impl From<image::ImageError> for ArtworkError {
+    fn from(err: image::ImageError) -> Self {
+        ArtworkError::ImageError(err)
+    }
+}
+
+impl From<std::io::Error> for ArtworkError {
+    fn from(err: std::io::Error) -> Self {
+        ArtworkError::IOError(err)
+    }
+}
+
+pub fn embedded_artwork(path: &std::path::Path) -> Option<Artwork> {
+    let tagged_file = lofty::read_from_path(path).ok()?;
+    let pictures = tagged_file.tags().iter().flat_map(|t| t.pictures());
+    let picture = pictures
+        .clone()
+        .find(|p| p.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.clone().next())?;
+
+    let mime = match image::guess_format(picture.data()) {
+        Ok(format) => format.to_mime_type().to_string(),
+        Err(_) => picture
+            .mime_type()
+            .map(|mime| mime.as_str().to_string())
+            .unwrap_or_else(|| "application/octet-stream".to_string()),
+    };
+
+    Some(Artwork {
+        data: picture.data().to_vec(),
+        mime,
+    })
+}
+
+// Thumbnails are keyed by the file version of the track the picture was taken
+// from, so a retagged file never serves a stale thumbnail. They are kept up to
+// `cache_size` bytes, 0 disables the cache.
+pub fn cover_art(
+    cache_dir: &std::path::Path,
+    cache_size: u64,
+    version: &str,
+    path: &std::path::Path,
+    size: Option<u32>,
+) -> Result<Artwork, ArtworkError> {
+    let artwork = || embedded_artwork(path).ok_or(ArtworkError::NotFound);
+    let Some(size) = size.filter(|size| *size > 0) else {
+        return artwork();
+    };
+
+    let thumbnails_dir = cache_dir.join("thumbnails");
+    for (ext, mime) in [
+        ("jpg", "image/jpeg"),
+        ("png", "image/png"),
+        ("webp", "image/webp"),
+    ] {
+        let thumbnail_path = thumbnails_dir.join(format!("{version}-{size}.{ext}"));
+        if let Ok(data) = std::fs::read(&thumbnail_path) {
+            // The modification time records the last use for eviction.
+            if let Ok(file) = std::fs::File::options().append(true).open(&thumbnail_path) {
+                let _ = file.set_modified(std::time::SystemTime::now());
+            }
+            return Ok(Artwork {
+                data,
+                mime: mime.to_string(),
+            });
+        }
+    }
+
+    let artwork = artwork()?;
+    let format = image::guess_format(&artwork.data)?;
+    let image = image::load_from_memory_with_format(&artwork.data, format)?;
+    if image.width() <= size && image.height() <= size {
+        return Ok(artwork);
+    }
+    let thumbnail = image.thumbnail(size, size);
+
+    let mut data = std::io::Cursor::new(vec![]);
+    let (ext, mime) = match format {
+        image::ImageFormat::Png => {
+            thumbnail.write_to(&mut data, image::ImageFormat::Png)?;
+            ("png", "image/png")
+        }
+        image::ImageFormat::WebP => {
+            thumbnail.write_to(&mut data, image::ImageFormat::WebP)?;
+            ("webp", "image/webp")
+        }
+        _ => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, 85);
+            thumbnail.to_rgb8().write_with_encoder(encoder)?;
+            ("jpg", "image/jpeg")
+        }
+    };
+    let data = data.into_inner();
+
+    if cache_size > 0 {
+        let file_name = format!("{version}-{size}.{ext}");
+        store_thumbnail(&thumbnails_dir, &file_name, &data)?;
+        evict_thumbnails(&thumbnails_dir, cache_size);
+    }
+
+    Ok(Artwork {
+        data,
+        mime: mime.to_string(),
+    })
+}
+
+fn store_thumbnail(
+    thumbnails_dir: &std::path::Path,
+    file_name: &str,
+    data: &[u8],
+) -> std::io::Result<()> {
+    std::fs::create_dir_all(thumbnails_dir)?;
+    let counter = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let pid = std::process::id();
+    let tmp_path = thumbnails_dir.join(format!(".{file_name}.{pid}-{counter}.tmp"));
+    let result = std::fs::File::create(&tmp_path).and_then(|mut tmp| {
+        tmp.write_all(data)?;
+        tmp.sync_all()?;
+        std::fs::rename(&tmp_path, thumbnails_dir.join(file_name))
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+// Removes the least recently used thumbnails until the rest fit in `cache_size`.
+fn evict_thumbnails(thumbnails_dir: &std::path::Path, cache_size: u64) {
+    let Ok(entries) = std::fs::read_dir(thumbnails_dir) else {
+        return;
+    };
+    let mut thumbnails: Vec<_> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                return None;
+            }
+            let metadata = entry.metadata().ok()?;
+            let last_used = metadata.modified().unwrap_or(std::time::UNIX_EPOCH);
+            Some((last_used, metadata.len(), entry.path()))
+        })
+        .collect();
+    let mut total: u64 = thumbnails.iter().map(|(_, len, _)| len).sum();
+    thumbnails.sort();
+    for (_, len, path) in thumbnails {
+        if total <= cache_size {
+            break;
+        }
+        if std::fs::remove_file(path).is_ok() {
+            total -= len;
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::conditional::unix_secs;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+
+const BOOKMARKS_FILE: &str = "bookmarks.json";
+
+// Positions in milliseconds, dates in seconds since the Unix epoch.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Bookmark {
+    pub id: String,
+    pub position: u64,
+    pub comment: Option<String>,
+    pub created: u64,
+    pub changed: u64,
+}
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct PlayQueue {
+    pub entries: Vec<String>,
+    pub current: Option<String>,
+    pub position: u64,
+    pub changed: u64,
+    pub changed_by: Option<String>,
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct Listeners {
+    bookmarks: HashMap<String, HashMap<String, Bookmark>>,
+    play_queues: HashMap<String, PlayQueue>,
+}
+
+#[derive(Debug)]
+pub enum BookmarkError {
+    IOError(std::io::Error),
+    SerdeJsonError(serde_json::Error),
+    PoisonError,
+}
+
+impl From<std::io::Error> for BookmarkError {
+    fn from(err: std::io::Error) -> Self {
+        BookmarkError::IOError(err)
+    }
+}
+
+impl From<serde_json::Error> for BookmarkError {
+    fn from(err: serde_json::Error) -> Self {
+        BookmarkError::SerdeJsonError(err)
+    }
+}
+
+// Resume positions and play queues per listener name, written to the
+// state directory after every change.
+pub struct Bookmarks {
+    state_dir: std::path::PathBuf,
+    listeners: Mutex<Listeners>,
+}
+
+impl Bookmarks {
+    pub fn load(state_dir: &std::path::Path) -> Bookmarks {
+        let listeners = std::fs::read(state_dir.join(BOOKMARKS_FILE))
+            .ok()
+            .and_then(|data| match serde_json::from_slice(&data) {
+                Ok(listeners) => Some(listeners),
+                Err(err) => {
+                    eprintln!("Failed to read the bookmarks: {err:?}");
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Bookmarks {
+            state_dir: state_dir.to_owned(),
+            listeners: Mutex::new(listeners),
+        }
+    }
+
+    pub fn bookmarks(&self, listener: &str) -> Result<Vec<Bookmark>, BookmarkError> {
+        let listeners = self.lock()?;
+        let mut bookmarks: Vec<Bookmark> = listeners
+            .bookmarks
+            .get(listener)
+            .map(|bookmarks| bookmarks.values().cloned().collect())
+            .unwrap_or_default();
+        bookmarks.sort_by_key(|bookmark| std::cmp::Reverse(bookmark.changed));
+        Ok(bookmarks)
+    }
+
+    // Creates the bookmark or moves an existing one for the same file.
+    pub fn save_bookmark(
+        &self,
+        listener: &str,
+        id: &str,
+        position: u64,
+        comment: Option<String>,
+    ) -> Result<Bookmark, BookmarkError> {
+        let mut listeners = self.lock()?;
+        let now = now();
+        let bookmark = listeners
+            .bookmarks
+            .entry(listener.to_owned())
+            .or_default()
+            .entry(id.to_owned())
+            .and_modify(|bookmark| {
+                bookmark.position = position;
+                bookmark.comment = comment.clone();
+                bookmark.changed = now;
+            })
+            .or_insert_with(|| Bookmark {
+                id: id.to_owned(),
+                position,
+                comment,
+                created: now,
+                changed: now,
+            })
+            .clone();
+        self.save(&listeners)?;
+        Ok(bookmark)
+    }
+
+    // Returns whether there was a bookmark to delete.
+    pub fn delete_bookmark(&self, listener: &str, id: &str) -> Result<bool, BookmarkError> {
+        let mut listeners = self.lock()?;
+        let Some(bookmarks) = listeners.bookmarks.get_mut(listener) else {
+            return Ok(false);
+        };
+        if bookmarks.remove(id).is_none() {
+            return Ok(false);
+        }
+        if bookmarks.is_empty() {
+            listeners.bookmarks.remove(listener);
+        }
+        self.save(&listeners)?;
+        Ok(true)
+    }
+
+    pub fn play_queue(&self, listener: &str) -> Result<Option<PlayQueue>, BookmarkError> {
+        Ok(self.lock()?.play_queues.get(listener).cloned())
+    }
+
+    // An empty queue removes the saved one.
+    pub fn save_play_queue(
+        &self,
+        listener: &str,
+        entries: Vec<String>,
+        current: Option<String>,
+        position: u64,
+        changed_by: Option<String>,
+    ) -> Result<Option<PlayQueue>, BookmarkError> {
+        let mut listeners = self.lock()?;
+        let play_queue = (!entries.is_empty()).then(|| PlayQueue {
+            entries,
+            current,
+            position,
+            changed: now(),
+            changed_by,
+        });
+        match &play_queue {
+            Some(play_queue) => {
+                listeners
+                    .play_queues
+                    .insert(listener.to_owned(), play_queue.clone());
+            }
+            None => {
+                listeners.play_queues.remove(listener);
+            }
+        }
+        self.save(&listeners)?;
+        Ok(play_queue)
+    }
+
+    // Rewrites the file IDs of bookmarks and queues after the ID mode changed,
+    // a bookmark that meets an existing one of the same file keeps the newer.
+    pub fn migrate(&self, mapping: &HashMap<String, String>) -> Result<(), BookmarkError> {
+        let mut listeners = self.lock()?;
+        let migrated = |id: &mut String| match mapping.get(id) {
+            Some(new) => {
+                *id = new.clone();
+                true
+            }
+            None => false,
+        };
+        let mut changed = false;
+        for bookmarks in listeners.bookmarks.values_mut() {
+            let mut entries: Vec<Bookmark> =
+                bookmarks.drain().map(|(_, bookmark)| bookmark).collect();
+            entries.sort_by_key(|bookmark| bookmark.changed);
+            for mut bookmark in entries {
+                changed |= migrated(&mut bookmark.id);
+                bookmarks.insert(bookmark.id.clone(), bookmark);
+            }
+        }
+        for play_queue in listeners.play_queues.values_mut() {
+            for entry in play_queue
+                .entries
+                .iter_mut()
+                .chain(play_queue.current.as_mut())
+            {
+                changed |= migrated(entry);
+            }
+        }
+        if changed {
+            self.save(&listeners)?;
+        }
+        Ok(())
+    }
+
+    fn save(&self, listeners: &Listeners) -> Result<(), BookmarkError> {
+        std::fs::create_dir_all(&self.state_dir)?;
+        let tmp_path = self.state_dir.join(format!(".{BOOKMARKS_FILE}.tmp"));
+        let mut tmp = std::fs::File::create(&tmp_path)?;
+        tmp.write_all(&serde_json::to_vec(listeners)?)?;
+        tmp.sync_all()?;
+        std::fs::rename(tmp_path, self.state_dir.join(BOOKMARKS_FILE))?;
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Listeners>, BookmarkError> {
+        self.listeners
+            .lock()
+            .map_err(|_| BookmarkError::PoisonError)
+    }
+}
+
+fn now() -> u64 {
+    unix_secs(std::time::SystemTime::now()).unwrap_or(0)
+}
@@ -0,0 +1,374 @@
+use crate::artwork::Artwork;
+use crate::id3;
+use std::io::{Read, Seek, SeekFrom};
+
+// Start and end in milliseconds.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Chapter {
+    pub title: Option<String>,
+    pub start: u64,
+    pub end: u64,
+    pub has_image: bool,
+}
+
+struct Parsed {
+    id: Vec<u8>,
+    title: Option<String>,
+    start: u64,
+    end: Option<u64>,
+    image: Option<Artwork>,
+}
+
+// Chapters come from MP4 chapter tracks or Nero `chpl` atoms and from
+// ID3v2 CHAP frames, ordered by the top-level CTOC when there is one.
+// Chapters without an end run until the next one or the end of the file.
+pub fn read_chapters(path: &std::path::Path, duration: u64) -> Vec<Chapter> {
+    parse(path, duration)
+        .into_iter()
+        .map(|parsed| Chapter {
+            title: parsed.title,
+            start: parsed.start,
+            end: parsed.end.unwrap_or(parsed.start),
+            has_image: parsed.image.is_some(),
+        })
+        .collect()
+}
+
+pub fn chapter_image(path: &std::path::Path, index: usize) -> Option<Artwork> {
+    parse(path, 0).into_iter().nth(index)?.image
+}
+
+fn parse(path: &std::path::Path, duration: u64) -> Vec<Parsed> {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return vec![];
+    };
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    let mut chapters = match extension.as_deref() {
+        Some("m4a" | "m4b") => mp4_chapters(&mut file),
+        _ => id3_chapters(&mut file),
+    }
+    .unwrap_or_default();
+
+    let starts: Vec<u64> = chapters.iter().map(|chapter| chapter.start).collect();
+    for chapter in chapters.iter_mut() {
+        if chapter.end.is_none_or(|end| end <= chapter.start) {
+            let next = starts.iter().filter(|start| **start > chapter.start).min();
+            chapter.end = Some(next.copied().unwrap_or(duration.max(chapter.start)));
+        }
+    }
+    chapters
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn be_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn id3_chapters(file: &mut std::fs::File) -> Option<Vec<Parsed>> {
+    let tag = id3::Tag::read(file)?;
+    let mut chapters = vec![];
+    let mut order: Option<Vec<Vec<u8>>> = None;
+    for (id, body) in tag.frames() {
+        match id {
+            b"CHAP" => chapters.extend(id3_chapter(body, tag.version)),
+            b"CTOC" => {
+                let Some((element_id, children)) = id3_toc(body) else {
+                    continue;
+                };
+                let top_level = body.get(element_id.len() + 1).is_some_and(|f| f & 2 != 0);
+                if top_level || order.is_none() {
+                    order = Some(children);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match order {
+        Some(order) => chapters.sort_by_key(|chapter| {
+            (
+                order
+                    .iter()
+                    .position(|id| *id == chapter.id)
+                    .unwrap_or(usize::MAX),
+                chapter.start,
+            )
+        }),
+        None => chapters.sort_by_key(|chapter| chapter.start),
+    }
+    Some(chapters)
+}
+
+fn id3_chapter(body: &[u8], version: u8) -> Option<Parsed> {
+    let id_end = body.iter().position(|byte| *byte == 0)?;
+    let start = be_u32(body, id_end + 1)? as u64;
+    let end = be_u32(body, id_end + 5)? as u64;
+
+    let mut title = None;
+    let mut image = None;
+    for (id, frame) in id3::frames(body.get(id_end + 17..)?, version) {
+        match id {
+            b"TIT2" => title = id3::text(frame),
+            b"APIC" => image = id3_picture(frame),
+            _ => {}
+        }
+    }
+    Some(Parsed {
+        id: body[..id_end].to_vec(),
+        title,
+        start,
+        end: Some(end),
+        image,
+    })
+}
+
+fn id3_toc(body: &[u8]) -> Option<(Vec<u8>, Vec<Vec<u8>>)> {
+    let id_end = body.iter().position(|byte| *byte == 0)?;
+    let count = *body.get(id_end + 2)?;
+    let mut rest = body.get(id_end + 3..)?;
+    let mut children = vec![];
+    for _ in 0..count {
+        let end = rest.iter().position(|byte| *byte == 0)?;
+        children.push(rest[..end].to_vec());
+        rest = &rest[end + 1..];
+    }
+    Some((body[..id_end].to_vec(), children))
+}
+
+fn id3_picture(frame: &[u8]) -> Option<Artwork> {
+    let (encoding, data) = frame.split_first()?;
+    let (mime, data) = id3::string(0, data);
+    let (_, data) = id3::string(*encoding, data.get(1..)?);
+    if data.is_empty() {
+        return None;
+    }
+    let mime = match image::guess_format(data) {
+        Ok(format) => format.to_mime_type().to_string(),
+        Err(_) if mime.contains('/') => mime,
+        Err(_) => "application/octet-stream".to_string(),
+    };
+    Some(Artwork {
+        data: data.to_vec(),
+        mime,
+    })
+}
+
+// Atoms larger than this are not read into memory.
+const MAX_ATOM_SIZE: u64 = 64 * 1024 * 1024;
+// Chapter tracks hold one sample per chapter, far fewer than this.
+const MAX_CHAPTER_SAMPLES: usize = 65_536;
+
+fn mp4_chapters(file: &mut std::fs::File) -> Option<Vec<Parsed>> {
+    let file_size = file.metadata().ok()?.len();
+    let mut offset = 0;
+    let moov = loop {
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header[..8]).ok()?;
+        let (size, header_size) = match be_u32(&header, 0)? {
+            0 => (file_size - offset, 8),
+            1 => {
+                file.read_exact(&mut header[8..]).ok()?;
+                (be_u64(&header, 8)?, 16)
+            }
+            size => (size as u64, 8),
+        };
+        if size < header_size {
+            return None;
+        }
+        if &header[4..8] == b"moov" {
+            if size > MAX_ATOM_SIZE {
+                return None;
+            }
+            let mut moov = vec![0u8; (size - header_size) as usize];
+            file.read_exact(&mut moov).ok()?;
+            break moov;
+        }
+        offset += size;
+    };
+
+    let tracks: Vec<&[u8]> = atoms(&moov)
+        .filter(|(kind, _)| kind == b"trak")
+        .map(|(_, trak)| trak)
+        .collect();
+    let chapter_tracks: Vec<u32> = tracks
+        .iter()
+        .filter_map(|trak| child(trak, &[b"tref", b"chap"]))
+        .flat_map(|chap| chap.chunks_exact(4).map(|id| be_u32(id, 0).unwrap_or(0)))
+        .collect();
+    let text_track = tracks.iter().find(|trak| {
+        track_id(trak).is_some_and(|id| chapter_tracks.contains(&id))
+            && child(trak, &[b"mdia", b"hdlr"])
+                .and_then(|hdlr| hdlr.get(8..12))
+                .is_some_and(|handler| handler == b"text")
+    });
+    if let Some(chapters) = text_track.and_then(|trak| text_track_chapters(file, trak)) {
+        return Some(chapters);
+    }
+
+    let chpl = child(&moov, &[b"udta", b"chpl"])?;
+    let mut position = if *chpl.first()? == 0 { 4 } else { 8 };
+    let count = *chpl.get(position)?;
+    position += 1;
+    let mut chapters = vec![];
+    for _ in 0..count {
+        let start = be_u64(chpl, position)? / 10_000;
+        let length = *chpl.get(position + 8)? as usize;
+        let title = chpl.get(position + 9..position + 9 + length)?;
+        position += 9 + length;
+        chapters.push(Parsed {
+            id: vec![],
+            title: Some(String::from_utf8_lossy(title).trim().to_string())
+                .filter(|title| !title.is_empty()),
+            start,
+            end: None,
+            image: None,
+        });
+    }
+    Some(chapters)
+}
+
+fn atoms(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        let (size, header_size) = match be_u32(data, 0)? {
+            0 => (data.len() as u64, 8),
+            1 => (be_u64(data, 8)?, 16),
+            size => (size as u64, 8),
+        };
+        if size < header_size || size > data.len() as u64 {
+            return None;
+        }
+        let (atom, rest) = data.split_at(size as usize);
+        data = rest;
+        Some((&atom[4..8], &atom[header_size as usize..]))
+    })
+}
+
+fn child<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().try_fold(data, |data, kind| {
+        atoms(data)
+            .find(|(atom, _)| atom == kind)
+            .map(|(_, body)| body)
+    })
+}
+
+fn track_id(trak: &[u8]) -> Option<u32> {
+    let tkhd = child(trak, &[b"tkhd"])?;
+    match tkhd.first()? {
+        1 => be_u32(tkhd, 20),
+        _ => be_u32(tkhd, 12),
+    }
+}
+
+// The entry count of a sample table, which has to fit in the rest of the
+// atom, so a corrupt count is rejected before it is iterated or allocated.
+fn table_len(atom: &[u8], offset: usize, entry_size: usize) -> Option<usize> {
+    let count = be_u32(atom, offset)? as usize;
+    let payload = atom.len().checked_sub(offset + 4)?;
+    (count.checked_mul(entry_size)? <= payload).then_some(count)
+}
+
+// QuickTime chapters are the samples of a text track, each holding
+// a length-prefixed title, timed by the track's sample durations.
+fn text_track_chapters(file: &mut std::fs::File, trak: &[u8]) -> Option<Vec<Parsed>> {
+    let mdhd = child(trak, &[b"mdia", b"mdhd"])?;
+    let timescale = match mdhd.first()? {
+        1 => be_u32(mdhd, 20)?,
+        _ => be_u32(mdhd, 12)?,
+    } as u64;
+    if timescale == 0 {
+        return None;
+    }
+    let stbl = child(trak, &[b"mdia", b"minf", b"stbl"])?;
+
+    // Sample counts are capped before anything is allocated for them,
+    // the durations by the number of samples the track has sizes for.
+    let stsz = child(stbl, &[b"stsz"])?;
+    let sizes: Vec<u32> = match be_u32(stsz, 4)? {
+        0 => (0..table_len(stsz, 8, 4)?.min(MAX_CHAPTER_SAMPLES))
+            .map(|index| be_u32(stsz, 12 + index * 4))
+            .collect::<Option<_>>()?,
+        size => vec![size; (be_u32(stsz, 8)? as usize).min(MAX_CHAPTER_SAMPLES)],
+    };
+
+    let stts = child(stbl, &[b"stts"])?;
+    let mut durations = vec![];
+    for entry in 0..table_len(stts, 4, 8)? {
+        let count = be_u32(stts, 8 + entry * 8)? as usize;
+        let delta = be_u32(stts, 12 + entry * 8)? as u64;
+        let count = count.min(sizes.len() - durations.len());
+        durations.extend(std::iter::repeat_n(delta, count));
+    }
+
+    let chunk_offsets: Vec<u64> = if let Some(stco) = child(stbl, &[b"stco"]) {
+        (0..table_len(stco, 4, 4)?)
+            .map(|index| be_u32(stco, 8 + index * 4).map(|offset| offset as u64))
+            .collect::<Option<_>>()?
+    } else {
+        let co64 = child(stbl, &[b"co64"])?;
+        (0..table_len(co64, 4, 8)?)
+            .map(|index| be_u64(co64, 8 + index * 8))
+            .collect::<Option<_>>()?
+    };
+
+    let stsc = child(stbl, &[b"stsc"])?;
+    let stsc_entries: Vec<(u32, u32)> = (0..table_len(stsc, 4, 12)?)
+        .map(|entry| {
+            Some((
+                be_u32(stsc, 8 + entry * 12)?,
+                be_u32(stsc, 12 + entry * 12)?,
+            ))
+        })
+        .collect::<Option<_>>()?;
+    let mut offsets = vec![];
+    for (chunk, chunk_offset) in chunk_offsets.iter().enumerate() {
+        let samples = stsc_entries
+            .iter()
+            .rev()
+            .find(|(first, _)| *first as usize <= chunk + 1)
+            .map_or(0, |(_, samples)| *samples);
+        let mut offset = *chunk_offset;
+        for _ in 0..samples {
+            let Some(size) = sizes.get(offsets.len()) else {
+                break;
+            };
+            offsets.push((offset, *size));
+            offset += *size as u64;
+        }
+    }
+
+    let mut chapters = vec![];
+    let mut time = 0;
+    for ((offset, size), duration) in offsets.into_iter().zip(durations) {
+        let mut sample = vec![0u8; size.min(64 * 1024) as usize];
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        file.read_exact(&mut sample).ok()?;
+        let length = sample.get(0..2).map_or(0, |length| {
+            u16::from_be_bytes([length[0], length[1]]) as usize
+        });
+        let text = sample.get(2..2 + length).unwrap_or_default();
+        let title = match text {
+            [0xfe, 0xff, ..] | [0xff, 0xfe, ..] => id3::string(1, text).0,
+            _ => String::from_utf8_lossy(text).to_string(),
+        };
+        chapters.push(Parsed {
+            id: vec![],
+            title: Some(title.trim().to_string()).filter(|title| !title.is_empty()),
+            start: time * 1000 / timescale,
+            end: Some((time + duration) * 1000 / timescale),
+            image: None,
+        });
+        time += duration;
+    }
+    Some(chapters)
+}
@@ -0,0 +1,114 @@
+use crate::AppState;
+use crate::settings::IdMode;
+use actix_web::http::header::{self, HttpDate};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use std::str::FromStr;
+
+// Whole-file IDs are the md5 of the contents, so anything served under them
+// never changes and can be cached indefinitely.
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+pub const REVALIDATE: &str = "no-cache";
+
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: Option<std::time::SystemTime>,
+    pub cache_control: &'static str,
+}
+
+impl Validators {
+    pub fn new(
+        etag: &str,
+        last_modified: Option<std::time::SystemTime>,
+        cache_control: &'static str,
+    ) -> Validators {
+        Validators {
+            etag: etag.to_owned(),
+            last_modified,
+            cache_control,
+        }
+    }
+
+    // Validators of a library file, the modification date comes from the hashing cache.
+    // Audio IDs survive retagging, so responses under them have to be revalidated.
+    pub fn file(data: &AppState, hash: &str, path: &std::path::Path) -> Validators {
+        let last_modified = data
+            .hashing_cache
+            .lock()
+            .ok()
+            .and_then(|cache| cache.get(path).map(|cached| cached.mod_date));
+        let cache_control = match data.settings.library.ids {
+            IdMode::File => IMMUTABLE,
+            IdMode::Audio => REVALIDATE,
+        };
+        Validators::new(&data.file_version(hash, path), last_modified, cache_control)
+    }
+
+    pub fn with_suffix(self, suffix: &str) -> Validators {
+        Validators {
+            etag: format!("{}-{suffix}", self.etag),
+            ..self
+        }
+    }
+
+    pub fn quoted_etag(&self) -> String {
+        format!("\"{}\"", self.etag)
+    }
+
+    // If-None-Match takes precedence, If-Modified-Since is only consulted without it.
+    pub fn not_modified(&self, req: &HttpRequest) -> bool {
+        if let Some(value) = header_str(req, header::IF_NONE_MATCH) {
+            let etag = self.quoted_etag();
+            return value
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag);
+        }
+        match (
+            header_str(req, header::IF_MODIFIED_SINCE),
+            self.last_modified,
+        ) {
+            (Some(value), Some(last_modified)) => HttpDate::from_str(value).is_ok_and(|since| {
+                unix_secs(last_modified).is_some_and(|secs| Some(secs) <= unix_secs(since.into()))
+            }),
+            _ => false,
+        }
+    }
+
+    pub fn apply(&self, response: &mut HttpResponseBuilder) {
+        response
+            .insert_header((header::ETAG, self.quoted_etag()))
+            .insert_header((header::CACHE_CONTROL, self.cache_control));
+        if let Some(last_modified) = self.last_modified {
+            response.insert_header((header::LAST_MODIFIED, HttpDate::from(last_modified)));
+        }
+    }
+
+    // Returns the 304 response when the client's copy is still current,
+    // otherwise a builder that already carries the validators.
+    pub fn response(&self, req: &HttpRequest) -> Result<HttpResponseBuilder, HttpResponse> {
+        let not_modified = self.not_modified(req);
+        let mut response = if not_modified {
+            HttpResponse::NotModified()
+        } else {
+            HttpResponse::Ok()
+        };
+        self.apply(&mut response);
+        if not_modified {
+            Err(response.finish())
+        } else {
+            Ok(response)
+        }
+    }
+}
+
+pub fn unix_secs(time: std::time::SystemTime) -> Option<u64> {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .ok()
+}
+
+pub fn header_str(req: &HttpRequest, name: header::HeaderName) -> Option<&str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
@@ -0,0 +1,329 @@
+// Loudness measurement following EBU R128 / ITU-R BS.1770-4.
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+// Block loudness is kept in a histogram of 0.1 LU bins from the absolute gate
+// to +5 LUFS, which is enough to gate single tracks and whole albums.
+const HISTOGRAM_STEP: f64 = 0.1;
+const HISTOGRAM_BINS: usize = 751;
+const TRUE_PEAK_TAPS: usize = 12;
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Measurement {
+    // Integrated loudness in LUFS, None when the audio is shorter than one block or silent.
+    pub integrated: Option<f64>,
+    // Linear amplitude, 1.0 is full scale.
+    pub true_peak: f64,
+    // Non-empty bins as (index, block count).
+    pub histogram: Vec<(u16, u64)>,
+}
+
+// The integrated loudness of histogram bins, accurate to the bin width.
+pub fn integrated(histogram: &[(u16, u64)]) -> Option<f64> {
+    let mean = |gate: f64| {
+        let (energy, count) = histogram
+            .iter()
+            .filter(|(bin, _)| bin_loudness(*bin) >= gate)
+            .fold((0.0, 0), |(energy, count), (bin, blocks)| {
+                (
+                    energy + energy_of(bin_loudness(*bin)) * *blocks as f64,
+                    count + blocks,
+                )
+            });
+        (count > 0).then(|| energy / count as f64)
+    };
+    let relative_gate = loudness_of(mean(ABSOLUTE_GATE)?) + RELATIVE_GATE;
+    mean(relative_gate).map(loudness_of)
+}
+
+// Sums the block counts of several measurements, as if their audio was played back to back.
+pub fn merge<'a>(histograms: impl Iterator<Item = &'a [(u16, u64)]>) -> Vec<(u16, u64)> {
+    let mut bins = vec![0u64; HISTOGRAM_BINS];
+    for (bin, blocks) in histograms.flatten() {
+        if let Some(count) = bins.get_mut(*bin as usize) {
+            *count += blocks;
+        }
+    }
+    sparse(&bins)
+}
+
+fn sparse(bins: &[u64]) -> Vec<(u16, u64)> {
+    bins.iter()
+        .enumerate()
+        .filter(|(_, blocks)| **blocks > 0)
+        .map(|(bin, blocks)| (bin as u16, *blocks))
+        .collect()
+}
+
+fn bin_loudness(bin: u16) -> f64 {
+    ABSOLUTE_GATE + (bin as f64 + 0.5) * HISTOGRAM_STEP
+}
+
+fn energy_of(loudness: f64) -> f64 {
+    10f64.powf((loudness + 0.691) / 10.0)
+}
+
+fn loudness_of(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[1] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[2] * y;
+        y
+    }
+}
+
+// The K-weighting pre-filter: a high shelf followed by a high pass,
+// with coefficients derived for any sample rate.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+// Surround channels count 1.41 times, the LFE channel of 5.1 is ignored.
+fn channel_weights(channels: usize) -> Vec<f64> {
+    match channels {
+        5 => vec![1.0, 1.0, 1.0, 1.41, 1.41],
+        6 => vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41],
+        _ => vec![1.0; channels],
+    }
+}
+
+// Oversamples with a windowed sinc to find peaks between samples.
+struct TruePeak {
+    factor: usize,
+    phases: Vec<[f64; TRUE_PEAK_TAPS]>,
+    history: Vec<[f64; TRUE_PEAK_TAPS]>,
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new(sample_rate: u32, channels: usize) -> TruePeak {
+        let factor = match sample_rate {
+            0..96_000 => 4,
+            96_000..192_000 => 2,
+            _ => 1,
+        };
+        let length = TRUE_PEAK_TAPS * factor;
+        let center = (length - 1) as f64 / 2.0;
+        let phases = (0..factor)
+            .map(|phase| {
+                let mut taps = [0.0; TRUE_PEAK_TAPS];
+                for (tap, value) in taps.iter_mut().enumerate() {
+                    let n = tap * factor + phase;
+                    let t = (n as f64 - center) / factor as f64;
+                    let sinc = if t == 0.0 {
+                        1.0
+                    } else {
+                        (std::f64::consts::PI * t).sin() / (std::f64::consts::PI * t)
+                    };
+                    let window = 0.5
+                        - 0.5
+                            * (2.0 * std::f64::consts::PI * (n as f64 + 0.5) / length as f64).cos();
+                    *value = sinc * window;
+                }
+                taps
+            })
+            .collect();
+        TruePeak {
+            factor,
+            phases,
+            history: vec![[0.0; TRUE_PEAK_TAPS]; channels],
+            peak: 0.0,
+        }
+    }
+
+    fn process(&mut self, channel: usize, sample: f64) {
+        self.peak = self.peak.max(sample.abs());
+        if self.factor == 1 {
+            return;
+        }
+        let history = &mut self.history[channel];
+        history.copy_within(0..TRUE_PEAK_TAPS - 1, 1);
+        history[0] = sample;
+        for taps in &self.phases {
+            let value: f64 = taps.iter().zip(history.iter()).map(|(t, x)| t * x).sum();
+            self.peak = self.peak.max(value.abs());
+        }
+    }
+}
+
+pub struct Meter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<[Biquad; 2]>,
+    true_peak: TruePeak,
+    // Gating blocks are 400 ms long and start every 100 ms,
+    // so they are built from the last four 100 ms sub-blocks.
+    sub_block_frames: usize,
+    frames: usize,
+    sum: f64,
+    sub_blocks: std::collections::VecDeque<f64>,
+    // Energies of the blocks above the absolute gate, for the exact track loudness.
+    blocks: Vec<f64>,
+    bins: Vec<u64>,
+}
+
+impl Meter {
+    pub fn new(sample_rate: u32, channels: usize) -> Meter {
+        Meter {
+            channels,
+            weights: channel_weights(channels),
+            filters: vec![k_weighting(sample_rate); channels],
+            true_peak: TruePeak::new(sample_rate, channels),
+            sub_block_frames: (sample_rate as usize / 10).max(1),
+            frames: 0,
+            sum: 0.0,
+            sub_blocks: std::collections::VecDeque::with_capacity(4),
+            blocks: vec![],
+            bins: vec![0; HISTOGRAM_BINS],
+        }
+    }
+
+    // Takes interleaved samples, a trailing partial frame is ignored.
+    pub fn add(&mut self, samples: &[i16]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let sample = *sample as f64 / 32768.0;
+                self.true_peak.process(channel, sample);
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let weighted = high_pass.process(shelf.process(sample));
+                self.sum += self.weights[channel] * weighted * weighted;
+            }
+            self.frames += 1;
+            if self.frames == self.sub_block_frames {
+                self.end_sub_block();
+            }
+        }
+    }
+
+    fn end_sub_block(&mut self) {
+        if self.sub_blocks.len() == 4 {
+            self.sub_blocks.pop_front();
+        }
+        self.sub_blocks
+            .push_back(self.sum / self.sub_block_frames as f64);
+        self.frames = 0;
+        self.sum = 0.0;
+
+        if self.sub_blocks.len() == 4 {
+            let energy = self.sub_blocks.iter().sum::<f64>() / 4.0;
+            let loudness = loudness_of(energy);
+            if loudness >= ABSOLUTE_GATE {
+                self.blocks.push(energy);
+                let bin = ((loudness - ABSOLUTE_GATE) / HISTOGRAM_STEP) as usize;
+                self.bins[bin.min(HISTOGRAM_BINS - 1)] += 1;
+            }
+        }
+    }
+
+    pub fn finish(self) -> Measurement {
+        let mean = |gate: f64| {
+            let gated = self
+                .blocks
+                .iter()
+                .filter(|energy| loudness_of(**energy) >= gate);
+            let count = gated.clone().count();
+            (count > 0).then(|| gated.sum::<f64>() / count as f64)
+        };
+        let integrated = mean(ABSOLUTE_GATE)
+            .and_then(|energy| mean(loudness_of(energy) + RELATIVE_GATE))
+            .map(loudness_of);
+        Measurement {
+            integrated,
+            true_peak: self.true_peak.peak,
+            histogram: sparse(&self.bins),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Interleaved stereo 997 Hz sine with the given peak level, 48 kHz.
+    fn stereo_sine(dbfs: f64, seconds: f64) -> Vec<i16> {
+        let amplitude = 10f64.powf(dbfs / 20.0) * 32767.0;
+        (0..(48_000.0 * seconds) as usize)
+            .flat_map(|n| {
+                let t = n as f64 / 48_000.0;
+                let sample = (amplitude * (2.0 * std::f64::consts::PI * 997.0 * t).sin()) as i16;
+                [sample, sample]
+            })
+            .collect()
+    }
+
+    fn measure(samples: &[i16]) -> Measurement {
+        let mut meter = Meter::new(48_000, 2);
+        meter.add(samples);
+        meter.finish()
+    }
+
+    // EBU Tech 3341 test case 1 allows ±0.1 LU.
+    #[test]
+    fn sine_at_minus_23_dbfs_measures_minus_23_lufs() {
+        let measurement = measure(&stereo_sine(-23.0, 10.0));
+        let loudness = measurement.integrated.unwrap();
+        assert!((loudness + 23.0).abs() < 0.1, "{loudness}");
+        let binned = integrated(&measurement.histogram).unwrap();
+        assert!((binned + 23.0).abs() < 0.1, "{binned}");
+        let peak = 10f64.powf(-23.0 / 20.0);
+        assert!((measurement.true_peak - peak).abs() < peak * 0.01);
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        let measurement = measure(&vec![0; 2 * 48_000 * 5]);
+        assert_eq!(measurement.integrated, None);
+        assert!(measurement.histogram.is_empty());
+        assert_eq!(integrated(&measurement.histogram), None);
+        assert_eq!(measurement.true_peak, 0.0);
+    }
+
+    #[test]
+    fn audio_below_the_absolute_gate_has_no_loudness() {
+        let measurement = measure(&stereo_sine(-80.0, 5.0));
+        assert_eq!(measurement.integrated, None);
+        assert_eq!(integrated(&measurement.histogram), None);
+    }
+
+    #[test]
+    fn audio_shorter_than_a_block_has_no_loudness() {
+        assert_eq!(measure(&stereo_sine(-23.0, 0.3)).integrated, None);
+    }
+}
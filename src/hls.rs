@@ -0,0 +1,293 @@
+use crate::conditional::Validators;
+use crate::range::read_file_chunk;
+use crate::seek::{id3v2_size, mp3_frame};
+use crate::settings::TranscodingSettings;
+use crate::transcode::{
+    Format, Position, Profile, TranscodeError, bitrate_for, decodable, transcode, transcode_into,
+};
+use crate::{AppState, TrackTags};
+use actix_web::{HttpRequest, HttpResponse, web};
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex};
+
+pub const SEGMENT_LENGTH: f64 = 10.0;
+pub const PLAYLIST_MIME: &str = "application/vnd.apple.mpegurl";
+
+// HLS segments are packed audio, which only works with MP3 and AAC.
+pub fn profile(
+    settings: &TranscodingSettings,
+    format: Option<&str>,
+    max_bit_rate: Option<u32>,
+) -> Result<Profile, TranscodeError> {
+    let packed =
+        |name: &str| Format::from_name(name).filter(|f| matches!(f, Format::Mp3 | Format::Aac));
+    let format = match format.filter(|format| !format.is_empty() && *format != "raw") {
+        Some(name) => packed(name).ok_or(TranscodeError::UnsupportedFormat(name.to_owned()))?,
+        None => packed(&settings.default_format).unwrap_or(Format::Aac),
+    };
+    if !settings.encoders.contains_key(format.name()) {
+        return Err(TranscodeError::NoEncoder(format));
+    }
+    Ok(Profile {
+        format,
+        bitrate: bitrate_for(settings, max_bit_rate),
+    })
+}
+
+// Segments are cut from the source file itself or from the transcoded stream.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    Source(Format),
+    Transcoded(Profile),
+}
+
+impl Encoding {
+    fn format(&self) -> Format {
+        match self {
+            Encoding::Source(format) => *format,
+            Encoding::Transcoded(profile) => profile.format,
+        }
+    }
+
+    // The path segment before the segment index, "raw" for the source.
+    fn variant(&self) -> String {
+        match self {
+            Encoding::Source(_) => "raw".to_owned(),
+            Encoding::Transcoded(profile) => profile.bitrate.to_string(),
+        }
+    }
+}
+
+// MP3 files are segmented as they are when they fit the requested format and
+// bitrate, or when the default format would need an encoder that is not set up,
+// so playlists work without any encoder. Everything else is transcoded.
+pub fn encoding(
+    settings: &TranscodingSettings,
+    path: &std::path::Path,
+    bitrate: Option<u32>,
+    format: Option<&str>,
+    max_bit_rate: Option<u32>,
+) -> Result<Encoding, TranscodeError> {
+    let max_bit_rate = max_bit_rate.filter(|max| *max > 0);
+    let fits = max_bit_rate.is_none_or(|max| bitrate.is_some_and(|bitrate| bitrate <= max));
+    let raw = format == Some("raw");
+    let requested = format.filter(|format| !format.is_empty() && *format != "raw");
+    let transcoded = profile(settings, requested, max_bit_rate);
+
+    if let Some(source) = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(Format::from_name)
+        .filter(|format| *format == Format::Mp3)
+    {
+        let wanted = requested.is_none_or(|name| Format::from_name(name) == Some(source));
+        let fallback = requested.is_none() && transcoded.is_err();
+        if wanted && (fits || raw || fallback) {
+            return Ok(Encoding::Source(source));
+        }
+    }
+    match transcoded {
+        Ok(_) if !decodable(path) => Err(TranscodeError::Undecodable),
+        transcoded => transcoded.map(Encoding::Transcoded),
+    }
+}
+
+// Seconds, taken from the library so that requests never parse the file.
+pub fn duration(tags: &TrackTags) -> Option<f64> {
+    let duration = match tags.duration_ms {
+        0 => tags.duration as f64,
+        ms => ms as f64 / 1000.0,
+    };
+    (duration > 0.0).then_some(duration)
+}
+
+pub fn segment_count(duration: f64) -> u64 {
+    (duration / SEGMENT_LENGTH).ceil() as u64
+}
+
+// Segment URIs are relative to the playlist and carry the resolved profile,
+// so every segment of a playlist is encoded the same way.
+pub fn playlist(duration: f64, encoding: Encoding) -> String {
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n",
+        SEGMENT_LENGTH.ceil() as u64
+    );
+    for index in 0..segment_count(duration) {
+        let length = (duration - index as f64 * SEGMENT_LENGTH).min(SEGMENT_LENGTH);
+        playlist.push_str(&format!(
+            "#EXTINF:{length:.3},\nhls/{}/{index}.{}\n",
+            encoding.variant(),
+            encoding.format().name()
+        ));
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+    playlist
+}
+
+// Packed audio segments start with an ID3 PRIV frame holding their start
+// on the 90 kHz MPEG-TS clock, which players use to line them up.
+fn timestamp_tag(start: f64) -> Vec<u8> {
+    const OWNER: &[u8] = b"com.apple.streaming.transportStreamTimestamp\0";
+    let timestamp = ((start * 90_000.0) as u64) & 0x1_ffff_ffff;
+    let frame_size = (OWNER.len() + 8) as u32;
+    let syncsafe = |size: u32| {
+        [
+            ((size >> 21) & 0x7f) as u8,
+            ((size >> 14) & 0x7f) as u8,
+            ((size >> 7) & 0x7f) as u8,
+            (size & 0x7f) as u8,
+        ]
+    };
+
+    let mut tag = vec![];
+    tag.extend_from_slice(b"ID3\x04\x00\x00");
+    tag.extend_from_slice(&syncsafe(10 + frame_size));
+    tag.extend_from_slice(b"PRIV");
+    tag.extend_from_slice(&syncsafe(frame_size));
+    tag.extend_from_slice(&[0, 0]);
+    tag.extend_from_slice(OWNER);
+    tag.extend_from_slice(&timestamp.to_be_bytes());
+    tag
+}
+
+pub async fn segment_response(
+    data: &AppState,
+    req: &HttpRequest,
+    hash: &str,
+    path: &std::path::Path,
+    profile: Profile,
+    index: u64,
+) -> Result<HttpResponse, TranscodeError> {
+    let transcoding = &data.settings.transcoding;
+    let start = index as f64 * SEGMENT_LENGTH;
+    let position = Position::Segment {
+        start,
+        length: SEGMENT_LENGTH,
+    };
+    let mime = profile.format.mime();
+
+    if data.transcode_cache.enabled() {
+        let key = format!(
+            "{hash}-{}-hls{index}.{}",
+            profile.bitrate,
+            profile.format.name()
+        );
+        return data
+            .transcode_cache
+            .serve(req, &key, mime, |mut writer| {
+                writer.write_all(&timestamp_tag(start))?;
+                transcode_into(transcoding, path, profile, position, writer)
+            })
+            .await;
+    }
+    let body = transcode(transcoding, path, profile, position)?.with_prefix(timestamp_tag(start));
+    Ok(HttpResponse::Ok().content_type(mime).body(body))
+}
+
+// Byte ranges of the source segments of an MP3 file with the time their
+// first frame starts at, indexed by segment.
+type SegmentTable = Vec<(u64, u64, f64)>;
+
+// Keeps the segment tables of the most recently played file versions,
+// so each segment request does not walk the frames of the whole file again.
+#[derive(Default)]
+pub struct SegmentTables {
+    tables: Mutex<VecDeque<(String, Arc<SegmentTable>)>>,
+}
+
+const CACHED_TABLES: usize = 16;
+
+impl SegmentTables {
+    async fn get(
+        &self,
+        version: String,
+        path: &std::path::Path,
+    ) -> std::io::Result<Arc<SegmentTable>> {
+        let cached = self.tables.lock().ok().and_then(|mut tables| {
+            let position = tables.iter().position(|(key, _)| *key == version)?;
+            let entry = tables.remove(position)?;
+            let table = entry.1.clone();
+            tables.push_front(entry);
+            Some(table)
+        });
+        if let Some(table) = cached {
+            return Ok(table);
+        }
+
+        let path = path.to_owned();
+        let table = Arc::new(
+            web::block(move || mp3_segments(&path))
+                .await
+                .map_err(std::io::Error::other)??,
+        );
+        if let Ok(mut tables) = self.tables.lock() {
+            tables.retain(|(key, _)| *key != version);
+            tables.push_front((version, table.clone()));
+            tables.truncate(CACHED_TABLES);
+        }
+        Ok(table)
+    }
+}
+
+// MP3 frames each hold a fixed number of samples, so a segment is the run of
+// whole frames that start within its time range.
+fn mp3_segments(path: &std::path::Path) -> std::io::Result<SegmentTable> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut offset = id3v2_size(&mut file).unwrap_or(0);
+    file.seek(SeekFrom::Start(offset))?;
+    let mut file = std::io::BufReader::new(file);
+    let mut position = offset;
+
+    let mut time = 0.0;
+    let mut segments = SegmentTable::new();
+    let mut header = [0u8; 4];
+    while offset + 4 <= len {
+        file.seek_relative(offset as i64 - position as i64)?;
+        file.read_exact(&mut header)?;
+        position = offset + 4;
+        // Anything that is not a frame header is skipped byte by byte until frames resume.
+        let Some(frame) = mp3_frame(&header) else {
+            offset += 1;
+            continue;
+        };
+        let frame_end = (offset + frame.len as u64).min(len);
+        let next_start = segments.len() as f64 * SEGMENT_LENGTH;
+        match segments.last_mut() {
+            Some(segment) if time < next_start => segment.1 = frame_end,
+            _ => segments.push((offset, frame_end, time)),
+        }
+        time += frame.samples as f64 / frame.sample_rate as f64;
+        offset = frame_end;
+    }
+    Ok(segments)
+}
+
+// Source segments never change for a file version, so they carry its validators.
+pub async fn source_segment_response(
+    data: &AppState,
+    req: &HttpRequest,
+    hash: &str,
+    path: &std::path::Path,
+    index: u64,
+) -> std::io::Result<HttpResponse> {
+    let segments = data
+        .hls_segments
+        .get(data.file_version(hash, path), path)
+        .await?;
+    let Some(&(first, end, start)) = segments.get(index as usize) else {
+        return Ok(HttpResponse::NotFound().body("Invalid segment"));
+    };
+    let validators = Validators::file(data, hash, path).with_suffix(&format!("hls{index}"));
+    let mut response = match validators.response(req) {
+        Ok(response) => response,
+        Err(not_modified) => return Ok(not_modified),
+    };
+
+    let file = std::fs::File::open(path)?;
+    let (_, frames) = read_file_chunk(file, first, end - first).await?;
+    let mut body = timestamp_tag(start);
+    body.extend_from_slice(&frames);
+    Ok(response.content_type(Format::Mp3.mime()).body(body))
+}
@@ -0,0 +1,130 @@
+use std::io::Read;
+
+// An ID3v2.3 or v2.4 tag at the start of a file, with unsynchronization
+// and the extended header removed.
+pub struct Tag {
+    pub version: u8,
+    data: Vec<u8>,
+}
+
+impl Tag {
+    pub fn read(file: &mut std::fs::File) -> Option<Tag> {
+        let mut header = [0u8; 10];
+        file.read_exact(&mut header).ok()?;
+        if &header[0..3] != b"ID3" || !matches!(header[3], 3 | 4) {
+            return None;
+        }
+        let version = header[3];
+        let flags = header[5];
+        let size = syncsafe(&header[6..10])?;
+        let mut data = vec![0u8; size as usize];
+        file.read_exact(&mut data).ok()?;
+        if flags & 0x80 != 0 {
+            data = resynchronize(&data);
+        }
+
+        if flags & 0x40 != 0 {
+            let start = match version {
+                4 => syncsafe(data.get(0..4)?)? as usize,
+                _ => be_u32(&data, 0)? as usize + 4,
+            };
+            data.drain(..start.min(data.len()));
+        }
+        Some(Tag { version, data })
+    }
+
+    pub fn frames(&self) -> Vec<(&[u8], &[u8])> {
+        frames(&self.data, self.version)
+    }
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn syncsafe(data: &[u8]) -> Option<u32> {
+    let data: [u8; 4] = data.try_into().ok()?;
+    Some(
+        data.iter()
+            .fold(0, |size, byte| (size << 7) | (*byte & 0x7f) as u32),
+    )
+}
+
+fn resynchronize(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    for (index, byte) in data.iter().enumerate() {
+        if *byte == 0 && index > 0 && data[index - 1] == 0xff {
+            continue;
+        }
+        result.push(*byte);
+    }
+    result
+}
+
+// Splits frames into their ID and body, also used for the subframes of chapters.
+pub fn frames(data: &[u8], version: u8) -> Vec<(&[u8], &[u8])> {
+    let mut frames = vec![];
+    let mut offset = 0;
+    while let Some(header) = data.get(offset..offset + 10) {
+        if header[0] == 0 {
+            break;
+        }
+        let size = match version {
+            4 => syncsafe(&header[4..8]),
+            _ => be_u32(header, 4),
+        };
+        let Some(body) = size.and_then(|size| data.get(offset + 10..offset + 10 + size as usize))
+        else {
+            break;
+        };
+        frames.push((&header[0..4], body));
+        offset += 10 + body.len();
+    }
+    frames
+}
+
+// Splits off a string in the given ID3 text encoding, returning it and the rest.
+pub fn string(encoding: u8, data: &[u8]) -> (String, &[u8]) {
+    let wide = matches!(encoding, 1 | 2);
+    let end = if wide {
+        (0..data.len() / 2)
+            .map(|index| index * 2)
+            .find(|index| data[*index] == 0 && data[*index + 1] == 0)
+    } else {
+        data.iter().position(|byte| *byte == 0)
+    };
+    let (text, rest) = match end {
+        Some(end) => (&data[..end], &data[end + if wide { 2 } else { 1 }..]),
+        None => (data, &data[data.len()..]),
+    };
+
+    let text = match encoding {
+        0 => text.iter().map(|byte| *byte as char).collect(),
+        1 | 2 => {
+            let (big_endian, text) = match text {
+                [0xff, 0xfe, text @ ..] => (false, text),
+                [0xfe, 0xff, text @ ..] => (true, text),
+                _ => (encoding == 2, text),
+            };
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|unit| match big_endian {
+                    true => u16::from_be_bytes([unit[0], unit[1]]),
+                    false => u16::from_le_bytes([unit[0], unit[1]]),
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(text).to_string(),
+    };
+    (text, rest)
+}
+
+pub fn text(frame: &[u8]) -> Option<String> {
+    let (encoding, data) = frame.split_first()?;
+    let (text, _) = string(*encoding, data);
+    let text = text.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
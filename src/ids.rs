@@ -0,0 +1,149 @@
+use crate::{AppState, HashingCache, audiofiles};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{HttpResponse, web};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::RwLock;
+
+const ALIASES_FILE: &str = "ids.json";
+const ALIASES_VERSION: u32 = 1;
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct AliasesFile {
+    version: u32,
+    aliases: HashMap<String, String>,
+}
+
+// IDs that files had before the ID mode changed, mapped to their current IDs,
+// so references that clients keep on their side still lead to the file.
+pub struct Aliases {
+    state_dir: std::path::PathBuf,
+    aliases: RwLock<HashMap<String, String>>,
+}
+
+impl Aliases {
+    pub fn load(state_dir: &std::path::Path) -> Aliases {
+        let aliases = std::fs::read(state_dir.join(ALIASES_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice::<AliasesFile>(&data).ok())
+            .filter(|file| file.version == ALIASES_VERSION)
+            .map(|file| file.aliases)
+            .unwrap_or_default();
+        Aliases {
+            state_dir: state_dir.to_owned(),
+            aliases: RwLock::new(aliases),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<String> {
+        self.aliases.read().ok()?.get(id).cloned()
+    }
+
+    pub fn all(&self) -> HashMap<String, String> {
+        self.aliases
+            .read()
+            .map(|aliases| aliases.clone())
+            .unwrap_or_default()
+    }
+
+    // Maps the ID of every file in `previous` to the ID of the same path in
+    // `cache`. Earlier aliases are pointed at the new IDs as well and aliases
+    // of IDs that are in use again are dropped, so switching back and forth
+    // never leads in circles. Returns the mapping of this migration.
+    pub fn migrate(
+        &self,
+        previous: &HashingCache,
+        cache: &HashingCache,
+    ) -> std::io::Result<HashMap<String, String>> {
+        let mapping: HashMap<String, String> = audiofiles(previous)
+            .into_iter()
+            .filter_map(|(old, path)| {
+                let new = &cache.get(&path)?.hash;
+                (old != *new).then(|| (old, new.clone()))
+            })
+            .collect();
+        let live: HashSet<&String> = cache.values().map(|cached| &cached.hash).collect();
+
+        let mut aliases = self
+            .aliases
+            .write()
+            .map_err(|_| std::io::Error::other("Poisoned ID aliases"))?;
+        for target in aliases.values_mut() {
+            if let Some(new) = mapping.get(target) {
+                *target = new.clone();
+            }
+        }
+        aliases.extend(mapping.clone());
+        aliases.retain(|old, new| old != new && !live.contains(old));
+
+        let data = serde_json::to_vec(&AliasesFile {
+            version: ALIASES_VERSION,
+            aliases: aliases.clone(),
+        })?;
+        std::fs::create_dir_all(&self.state_dir)?;
+        let tmp_path = self.state_dir.join(format!(".{ALIASES_FILE}.tmp"));
+        let mut tmp = std::fs::File::create(&tmp_path)?;
+        tmp.write_all(&data)?;
+        tmp.sync_all()?;
+        std::fs::rename(tmp_path, self.state_dir.join(ALIASES_FILE))?;
+        Ok(mapping)
+    }
+}
+
+// Answers requests for an old file ID with a permanent redirect to the current
+// one, which keeps the method and lets clients update what they stored.
+pub async fn redirect_aliases<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>, actix_web::Error> {
+    if let Some(data) = req.app_data::<web::Data<AppState>>()
+        && let Some(location) = aliased_location(&data.aliases, req.uri())
+    {
+        let response = HttpResponse::PermanentRedirect()
+            .insert_header((header::LOCATION, location))
+            .finish();
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+// File IDs appear as the path segment after /file/ and /bookmarks/, and in the
+// `id` and `current` parameters of the Subsonic API.
+fn aliased_location(aliases: &Aliases, uri: &actix_web::http::Uri) -> Option<String> {
+    let path = uri.path();
+    let query = uri.query();
+    let segments: Vec<&str> = path.split('/').collect();
+    if let ["", kind @ ("file" | "bookmarks"), id, rest @ ..] = segments.as_slice()
+        && let Some(new) = aliases.get(id)
+    {
+        let path = [&["", kind, new.as_str()], rest].concat().join("/");
+        return Some(match query {
+            Some(query) => format!("{path}?{query}"),
+            None => path,
+        });
+    }
+
+    if !path.starts_with("/rest/") {
+        return None;
+    }
+    let mut changed = false;
+    let parameters: Vec<String> = query?
+        .split('&')
+        .map(|parameter| match parameter.split_once('=') {
+            Some((name @ ("id" | "current"), id)) => match aliases.get(id) {
+                Some(new) => {
+                    changed = true;
+                    format!("{name}={new}")
+                }
+                None => parameter.to_owned(),
+            },
+            _ => parameter.to_owned(),
+        })
+        .collect();
+    changed.then(|| format!("{path}?{}", parameters.join("&")))
+}
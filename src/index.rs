@@ -0,0 +1,115 @@
+use crate::settings::IdMode;
+use crate::{CachedFileHash, HashingCache};
+use std::io::{BufRead, Write};
+
+const INDEX_FILE: &str = "index.jsonl";
+// Bump when hashes or stored tags change meaning, older indexes are then ignored.
+const INDEX_VERSION: u32 = 1;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Header {
+    version: u32,
+    // Indexes written before audio IDs existed hold whole-file IDs.
+    #[serde(default)]
+    ids: IdMode,
+}
+
+pub struct Index {
+    pub ids: IdMode,
+    pub cache: HashingCache,
+}
+
+#[derive(serde::Serialize)]
+struct EntryRef<'a> {
+    path: &'a str,
+    #[serde(flatten)]
+    file: &'a CachedFileHash,
+}
+
+#[derive(serde::Deserialize)]
+struct Entry {
+    path: std::path::PathBuf,
+    #[serde(flatten)]
+    file: CachedFileHash,
+}
+
+// The index is a version header followed by one JSON line per file, so a damaged
+// line only costs that file being hashed again. A missing, unreadable or
+// outdated index starts the library from scratch.
+pub fn load(state_dir: &std::path::Path) -> Index {
+    let mut index = Index {
+        ids: IdMode::default(),
+        cache: HashingCache::new(),
+    };
+    let Ok(file) = std::fs::File::open(state_dir.join(INDEX_FILE)) else {
+        return index;
+    };
+    let mut lines = std::io::BufReader::new(file).lines();
+    let header = lines
+        .next()
+        .and_then(|line| line.ok())
+        .and_then(|line| serde_json::from_str::<Header>(&line).ok());
+    match header {
+        Some(header) if header.version == INDEX_VERSION => index.ids = header.ids,
+        Some(header) => {
+            eprintln!("Ignoring the library index of version {}", header.version);
+            return index;
+        }
+        None => {
+            eprintln!("Ignoring the library index without a valid header");
+            return index;
+        }
+    }
+
+    let mut skipped = 0;
+    for line in lines {
+        match line
+            .ok()
+            .and_then(|line| serde_json::from_str::<Entry>(&line).ok())
+        {
+            Some(entry) => {
+                index.cache.insert(entry.path, entry.file);
+            }
+            None => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        eprintln!("Skipped {skipped} damaged entries of the library index");
+    }
+    index
+}
+
+// Paths that are not valid UTF-8 cannot be stored in JSON and are hashed on every start.
+pub fn save(state_dir: &std::path::Path, ids: IdMode, cache: &HashingCache) -> std::io::Result<()> {
+    let mut entries: Vec<EntryRef> = cache
+        .iter()
+        .filter_map(|(path, file)| {
+            Some(EntryRef {
+                path: path.to_str()?,
+                file,
+            })
+        })
+        .collect();
+    entries.sort_by_key(|entry| entry.path);
+
+    std::fs::create_dir_all(state_dir)?;
+    let tmp_path = state_dir.join(format!(".{INDEX_FILE}.tmp"));
+    let tmp = std::fs::File::create(&tmp_path)?;
+    let mut writer = std::io::BufWriter::new(&tmp);
+    serde_json::to_writer(
+        &mut writer,
+        &Header {
+            version: INDEX_VERSION,
+            ids,
+        },
+    )?;
+    writer.write_all(b"\n")?;
+    for entry in entries {
+        serde_json::to_writer(&mut writer, &entry)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    drop(writer);
+    tmp.sync_all()?;
+    std::fs::rename(tmp_path, state_dir.join(INDEX_FILE))
+}
@@ -1,20 +1,121 @@
+use lofty::{
+    file::{AudioFile as LofyAudioFile, TaggedFileExt},
+    tag::Accessor,
+};
 use md5::{Digest, Md5};
+use settings::IdMode;
 use std::io::{Read, Seek};
 use std::str::FromStr;
-use std::sync::Mutex;
-
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, RwLock};
+
+pub mod analysis;
+pub mod artwork;
+pub mod bookmarks;
+pub mod chapters;
+pub mod conditional;
+pub mod ebur128;
+pub mod hls;
+pub mod id3;
+pub mod ids;
+pub mod index;
+pub mod library;
+pub mod loudness;
+pub mod lyrics;
+pub mod payload;
+pub mod random;
+pub mod range;
+pub mod scan;
+pub mod search;
+pub mod seek;
 pub mod services;
+pub mod settings;
+pub mod subsonic;
+#[cfg(test)]
+mod test_dir;
+pub mod transcode;
+pub mod transcode_cache;
+pub mod watch;
 
 pub struct AppState {
     pub base_dir: String,
-    pub audiofiles: Mutex<std::collections::HashMap<String, std::path::PathBuf>>,
-    pub hashing_cache: Mutex<std::collections::HashMap<std::path::PathBuf, CachedFileHash>>,
+    pub cache_dir: std::path::PathBuf,
+    pub state_dir: std::path::PathBuf,
+    pub library: RwLock<Arc<library::Library>>,
+    pub hashing_cache: Mutex<HashingCache>,
+    pub users: std::collections::HashMap<String, String>,
+    pub scan_progress: scan::ScanProgress,
+    pub settings: settings::Settings,
+    pub transcode_cache: transcode_cache::TranscodeCache,
+    pub bookmarks: bookmarks::Bookmarks,
+    pub loudness: analysis::LoudnessStore,
+    pub aliases: ids::Aliases,
+    pub hls_segments: hls::SegmentTables,
+}
+
+impl AppState {
+    // Readers clone the current snapshot and never wait for a scan,
+    // a finished scan replaces the whole snapshot at once.
+    pub fn library(&self) -> Result<Arc<library::Library>, services::ServiceError> {
+        self.library
+            .read()
+            .map(|library| library.clone())
+            .map_err(|_| services::ServiceError::PoisonError)
+    }
+
+    pub fn set_library(&self, library: library::Library) -> Result<(), services::ServiceError> {
+        *self
+            .library
+            .write()
+            .map_err(|_| services::ServiceError::PoisonError)? = Arc::new(library);
+        Ok(())
+    }
+
+    // Stands for the exact bytes of a file. Whole-file IDs already do, audio
+    // IDs stay the same when tags change and get the size and modification date added.
+    pub fn file_version(&self, hash: &str, path: &std::path::Path) -> String {
+        if self.settings.library.ids == IdMode::File {
+            return hash.to_owned();
+        }
+        let stat = self.hashing_cache.lock().ok().and_then(|cache| {
+            let cached = cache.get(path)?;
+            let modified = cached.mod_date.duration_since(std::time::UNIX_EPOCH).ok()?;
+            Some((cached.size, modified.as_nanos()))
+        });
+        match stat {
+            Some((size, modified)) => format!("{hash}-{size:x}-{modified:x}"),
+            None => hash.to_owned(),
+        }
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct CachedFileHash {
     pub hash: String,
+    pub size: u64,
     pub mod_date: std::time::SystemTime,
+    pub tags: TrackTags,
+}
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<u32>,
+    pub track: Option<u32>,
+    pub disc: Option<u32>,
+    pub duration: u64,
+    // The same in milliseconds, 0 in indexes written before it was recorded.
+    pub duration_ms: u64,
+    pub bitrate: Option<u32>,
+    pub size: u64,
+    pub has_artwork: bool,
+    pub chapters: Vec<chapters::Chapter>,
+    pub replay_gain: loudness::ReplayGain,
 }
 
 #[derive(serde::Serialize)]
@@ -32,7 +133,52 @@ pub struct AudioFileMetadata {
     pub genre: Option<String>,
     pub release_year: Option<String>,
     pub artwork_url: Option<String>,
+    pub lyrics_url: Option<String>,
     pub duration: u64,
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+    pub header_gain: Option<f64>,
+    pub loudness: Option<f64>,
+    pub album_loudness: Option<f64>,
+}
+
+// Start and end in seconds.
+#[derive(serde::Serialize)]
+pub struct AudioFileChapter {
+    pub title: Option<String>,
+    pub start: f64,
+    pub end: f64,
+    pub image_url: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SearchArtist {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(serde::Serialize)]
+pub struct SearchAlbum {
+    pub id: String,
+    pub name: String,
+    pub artist: String,
+}
+
+// Size of a single copy in bytes, the canonical path comes first.
+#[derive(serde::Serialize)]
+pub struct DuplicateGroup {
+    pub id: String,
+    pub size: u64,
+    pub paths: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+pub struct SearchResponse {
+    pub artists: Vec<SearchArtist>,
+    pub albums: Vec<SearchAlbum>,
+    pub files: Vec<AudioFile>,
 }
 
 #[derive(serde::Serialize)]
@@ -45,6 +191,10 @@ pub struct PingResponse {
 pub enum ProgramOption {
     BaseDir(std::path::PathBuf),
     Port(u16),
+    User(String, String),
+    CacheDir(std::path::PathBuf),
+    StateDir(std::path::PathBuf),
+    Config(std::path::PathBuf),
     PrintHelp,
 }
 
@@ -75,78 +225,135 @@ impl From<std::io::Error> for TraverseError {
 
 pub fn is_audiofile(path: std::path::PathBuf) -> bool {
     if let Some(ext) = path.extension() {
-        if ext == "m4b" {
-            return true;
-        } else if ext == "m4a" {
-            return true;
-        } else if ext == "mp3" {
-            return true;
-        } else if ext == "flac" {
-            return true;
-        } else if ext == "wav" {
-            return true;
-        } else if ext == "opus" {
-            return true;
-        }
+        return matches!(
+            ext.to_str(),
+            Some("m4b" | "m4a" | "mp3" | "flac" | "wav" | "opus")
+        );
     }
 
     false
 }
 
+pub type AudioFiles = std::collections::HashMap<String, std::path::PathBuf>;
+pub type HashingCache = std::collections::HashMap<std::path::PathBuf, CachedFileHash>;
+
 pub fn traverse_dir(
     base_dir: &str,
-    mut cache: std::collections::HashMap<std::path::PathBuf, CachedFileHash>,
-) -> Result<
-    (
-        std::collections::HashMap<String, std::path::PathBuf>,
-        std::collections::HashMap<std::path::PathBuf, CachedFileHash>,
-    ),
-    TraverseError,
-> {
+    mut cache: HashingCache,
+    ids: IdMode,
+    progress: &scan::ScanProgress,
+) -> Result<(AudioFiles, HashingCache), TraverseError> {
     let base_dir_path = std::path::PathBuf::from_str(base_dir)
-        .expect(&format!("Infallible: from_str({base_dir:?}) to PathBuf"));
-    let mut dir_list = vec![base_dir_path];
+        .unwrap_or_else(|_| panic!("Infallible: from_str({base_dir:?}) to PathBuf"));
+    let audiofiles_paths = walk_dir(&base_dir_path, progress)?;
+    progress
+        .files
+        .store(audiofiles_paths.len() as u64, Ordering::Relaxed);
+
+    let duration = std::time::SystemTime::now();
+    let seen: std::collections::HashSet<&std::path::PathBuf> = audiofiles_paths.iter().collect();
+    let gone: Vec<(std::path::PathBuf, CachedFileHash)> =
+        cache.extract_if(|path, _| !seen.contains(path)).collect();
+    reuse_moved(&mut cache, gone, &audiofiles_paths);
+    update_files(&mut cache, audiofiles_paths, ids, progress)?;
+    println!("{:?}", duration.elapsed().map(|d| d.as_secs_f64()));
+
+    Ok((audiofiles(&cache), cache))
+}
+
+// Collects the audio files below `dir`, only an unreadable `dir` itself is an error.
+pub fn walk_dir(
+    dir: &std::path::Path,
+    progress: &scan::ScanProgress,
+) -> Result<Vec<std::path::PathBuf>, TraverseError> {
+    let mut dir_list = vec![dir.to_owned()];
     let mut audiofiles_paths = Vec::new();
-    while dir_list.len() > 0 {
-        let path = dir_list.pop().expect("dir_list should not be empty");
-        let entries = std::fs::read_dir(path)?;
-        for entry in entries {
-            if let Ok(file) = entry {
-                if let Ok(metadata) = std::fs::metadata(file.path()) {
-                    if metadata.is_file() && is_audiofile(file.path()) {
-                        audiofiles_paths.push(file.path());
-                    } else if metadata.is_dir() {
-                        dir_list.push(file.path());
-                    }
+    while let Some(path) = dir_list.pop() {
+        let entries = match std::fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(err) if path != dir => {
+                progress.error(format!("{path:?}: {err}"));
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        progress.directories.fetch_add(1, Ordering::Relaxed);
+        for file in entries.flatten() {
+            if let Ok(metadata) = std::fs::metadata(file.path()) {
+                if metadata.is_file() && is_audiofile(file.path()) {
+                    audiofiles_paths.push(file.path());
+                } else if metadata.is_dir() {
+                    dir_list.push(file.path());
                 }
             }
         }
     }
+    Ok(audiofiles_paths)
+}
 
-    let duration = std::time::SystemTime::now();
-    let mut cached: std::collections::HashMap<String, std::path::PathBuf> =
-        std::collections::HashMap::new();
+// Moved and renamed files keep their hash, they are recognized among
+// the entries that are gone by file name, size and modification date.
+pub fn reuse_moved(
+    cache: &mut HashingCache,
+    gone: Vec<(std::path::PathBuf, CachedFileHash)>,
+    paths: &[std::path::PathBuf],
+) {
+    type Key = (std::ffi::OsString, u64, std::time::SystemTime);
+    let mut gone: std::collections::HashMap<Key, CachedFileHash> = gone
+        .into_iter()
+        .filter_map(|(path, cached)| {
+            let key = (path.file_name()?.to_owned(), cached.size, cached.mod_date);
+            Some((key, cached))
+        })
+        .collect();
+    if gone.is_empty() {
+        return;
+    }
+    for path in paths {
+        if cache.contains_key(path) {
+            continue;
+        }
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        let Ok((size, modified)) =
+            std::fs::metadata(path).and_then(|m| Ok((m.len(), m.modified()?)))
+        else {
+            continue;
+        };
+        if let Some(cached) = gone.remove(&(name.to_owned(), size, modified)) {
+            cache.insert(path.clone(), cached);
+        }
+    }
+}
 
-    let mut audiofiles_paths = audiofiles_paths
+// Files keep their hash while size and modification date are unchanged,
+// the others are hashed again and dropped from the cache when that fails.
+// Returns the number of files that were hashed.
+pub fn update_files(
+    cache: &mut HashingCache,
+    paths: Vec<std::path::PathBuf>,
+    ids: IdMode,
+    progress: &scan::ScanProgress,
+) -> Result<usize, TraverseError> {
+    let mut audiofiles_paths = paths
         .into_iter()
         .filter(|path| {
-            if let Some(fhc) = cache.get(path) {
-                if let Ok(metadata) = std::fs::metadata(path) {
-                    let modified = metadata.modified();
-                    if modified.is_ok_and(|m| m == fhc.mod_date) {
-                        cached.insert(fhc.hash.clone(), path.clone());
-                        false
-                    } else {
-                        true
-                    }
-                } else {
-                    false
-                }
-            } else {
-                true
+            let Some(fhc) = cache.get(path) else {
+                return true;
+            };
+            let unchanged = std::fs::metadata(path).is_ok_and(|metadata| {
+                metadata.len() == fhc.size && metadata.modified().is_ok_and(|m| m == fhc.mod_date)
+            });
+            if unchanged {
+                progress.reused.fetch_add(1, Ordering::Relaxed);
             }
+            !unchanged
         })
         .collect::<Vec<std::path::PathBuf>>();
+    for path in &audiofiles_paths {
+        cache.remove(path);
+    }
 
     let audiofiles_paths_len = audiofiles_paths.len();
     let workers = std::thread::available_parallelism()
@@ -154,53 +361,135 @@ pub fn traverse_dir(
         .unwrap_or(2)
         - 1;
 
-    let audiofiles: Result<
-        std::collections::HashMap<std::string::String, std::path::PathBuf>,
-        TraverseError,
-    > = crossbeam::scope(|scope| {
-        let mut audiofiles = std::collections::HashMap::new();
-
-        let mut handles = vec![];
-        for _ in 0..workers {
-            let split_index = audiofiles_paths.len() - (audiofiles_paths_len / (workers));
-            let chunk = audiofiles_paths.split_off(split_index);
-            let handle = scope.spawn(move |_| {
-                let mut audiofiles = vec![];
-                for path in chunk {
-                    audiofiles.push((hex_encode(md5_hash(&path)?), path));
+    // Size and modification date are taken before hashing, so a file
+    // changed meanwhile is hashed again on the next scan.
+    let hash_file = |path: std::path::PathBuf| {
+        let (size, mod_date) =
+            match std::fs::metadata(&path).and_then(|m| Ok((m.len(), m.modified()?))) {
+                Ok(stat) => stat,
+                Err(err) => {
+                    progress.error(format!("{path:?}: {err}"));
+                    return None;
                 }
-                Ok(audiofiles)
-            });
-            handles.push(handle);
-        }
-        for path in audiofiles_paths {
-            audiofiles.insert(hex_encode(md5_hash(&path)?), path);
-        }
-        for handle in handles {
-            let files: Result<Vec<(String, std::path::PathBuf)>, TraverseError> = handle
-                .join()
-                .map_err(|err| TraverseError::ThreadError(format!("{err:?}")))?;
-            audiofiles.extend(files?);
+            };
+        match md5_hash(&path, ids) {
+            Ok(hash) => {
+                progress.hashed.fetch_add(1, Ordering::Relaxed);
+                let tags = read_tags(&path);
+                Some((
+                    path,
+                    CachedFileHash {
+                        hash: hex_encode(hash),
+                        size,
+                        mod_date,
+                        tags,
+                    },
+                ))
+            }
+            Err(err) => {
+                progress.error(format!("{err:?}"));
+                None
+            }
         }
+    };
+
+    let hashed: Result<Vec<(std::path::PathBuf, CachedFileHash)>, TraverseError> =
+        crossbeam::scope(|scope| {
+            let mut hashed = vec![];
+
+            let mut handles = vec![];
+            for _ in 0..workers {
+                let split_index = audiofiles_paths.len() - (audiofiles_paths_len / (workers));
+                let chunk = audiofiles_paths.split_off(split_index);
+                let handle = scope.spawn(move |_| {
+                    chunk
+                        .into_iter()
+                        .filter_map(hash_file)
+                        .collect::<Vec<(std::path::PathBuf, CachedFileHash)>>()
+                });
+                handles.push(handle);
+            }
+            hashed.extend(audiofiles_paths.into_iter().filter_map(hash_file));
+            for handle in handles {
+                let files = handle
+                    .join()
+                    .map_err(|err| TraverseError::ThreadError(format!("{err:?}")))?;
+                hashed.extend(files);
+            }
 
-        Ok(audiofiles)
-    })
-    .map_err(|err| TraverseError::ThreadError(format!("{err:?}")))?;
-    let mut audiofiles = audiofiles?;
-    for (hash, path) in audiofiles.iter() {
-        let metadata = std::fs::metadata(path)?;
-        cache.insert(
-            path.to_path_buf(),
-            CachedFileHash {
-                hash: hash.clone(),
-                mod_date: metadata.modified()?,
-            },
-        );
+            Ok(hashed)
+        })
+        .map_err(|err| TraverseError::ThreadError(format!("{err:?}")))?;
+    let hashed = hashed?;
+    let count = hashed.len();
+    cache.extend(hashed);
+    Ok(count)
+}
+
+// Identical files share an ID. Their paths are ordered by modification date,
+// so the original comes before later copies, and then by path. The first
+// path is the canonical one, which is served and listed.
+pub fn paths_by_hash(
+    cache: &HashingCache,
+) -> std::collections::HashMap<String, Vec<std::path::PathBuf>> {
+    let mut files: Vec<(&std::path::PathBuf, &CachedFileHash)> = cache.iter().collect();
+    files.sort_by_key(|(path, cached)| (cached.mod_date, *path));
+    let mut paths = std::collections::HashMap::<String, Vec<std::path::PathBuf>>::new();
+    for (path, cached) in files {
+        paths
+            .entry(cached.hash.clone())
+            .or_default()
+            .push(path.clone());
     }
-    audiofiles.extend(cached);
-    println!("{:?}", duration.elapsed().map(|d| d.as_secs_f64()));
+    paths
+}
 
-    Ok((audiofiles, cache))
+pub fn audiofiles(cache: &HashingCache) -> AudioFiles {
+    paths_by_hash(cache)
+        .into_iter()
+        .filter_map(|(hash, paths)| Some((hash, paths.into_iter().next()?)))
+        .collect()
+}
+
+pub fn read_tags(path: &std::path::Path) -> TrackTags {
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let Ok(tagged_file) = lofty::read_from_path(path) else {
+        return TrackTags {
+            size,
+            chapters: chapters::read_chapters(path, 0),
+            ..Default::default()
+        };
+    };
+    let tags = tagged_file.tags();
+    let non_empty = |value: std::borrow::Cow<'_, str>| {
+        let value = value.trim().to_string();
+        (!value.is_empty()).then_some(value)
+    };
+
+    TrackTags {
+        title: tags.iter().find_map(|t| t.title().and_then(non_empty)),
+        artist: tags.iter().find_map(|t| t.artist().and_then(non_empty)),
+        album: tags.iter().find_map(|t| t.album().and_then(non_empty)),
+        album_artist: tags.iter().find_map(|t| {
+            t.get_string(lofty::tag::ItemKey::AlbumArtist)
+                .map(std::borrow::Cow::Borrowed)
+                .and_then(non_empty)
+        }),
+        genre: tags.iter().find_map(|t| t.genre().and_then(non_empty)),
+        year: tags.iter().find_map(|t| t.date()).map(|d| d.year as u32),
+        track: tags.iter().find_map(|t| t.track()),
+        disc: tags.iter().find_map(|t| t.disk()),
+        duration: tagged_file.properties().duration().as_secs(),
+        duration_ms: tagged_file.properties().duration().as_millis() as u64,
+        bitrate: tagged_file.properties().audio_bitrate(),
+        size,
+        has_artwork: tags.iter().any(|t| t.picture_count() != 0),
+        chapters: chapters::read_chapters(
+            path,
+            tagged_file.properties().duration().as_millis() as u64,
+        ),
+        replay_gain: loudness::read_replay_gain(path, &tagged_file),
+    }
 }
 
 pub fn extension_to_mime(file_ext: &std::ffi::OsStr) -> Option<String> {
@@ -236,6 +525,32 @@ pub fn process_args() -> Result<Vec<ProgramOption>, Error> {
                     Err(Error::InvalidOption(arg))
                 }
             }
+            s if s.starts_with("--user=") => {
+                match s.split_once('=').and_then(|(_, s)| s.split_once(':')) {
+                    Some((name, password)) if !name.is_empty() => {
+                        Ok(ProgramOption::User(name.to_owned(), password.to_owned()))
+                    }
+                    _ => Err(Error::InvalidOption(arg)),
+                }
+            }
+            s if s.starts_with("--cache-dir=") => match s.split_once('=') {
+                Some((_, path)) if !path.is_empty() => {
+                    Ok(ProgramOption::CacheDir(std::path::PathBuf::from(path)))
+                }
+                _ => Err(Error::InvalidOption(arg)),
+            },
+            s if s.starts_with("--state-dir=") => match s.split_once('=') {
+                Some((_, path)) if !path.is_empty() => {
+                    Ok(ProgramOption::StateDir(std::path::PathBuf::from(path)))
+                }
+                _ => Err(Error::InvalidOption(arg)),
+            },
+            s if s.starts_with("--config=") => match s.split_once('=') {
+                Some((_, path)) if !path.is_empty() => {
+                    Ok(ProgramOption::Config(std::path::PathBuf::from(path)))
+                }
+                _ => Err(Error::InvalidOption(arg)),
+            },
             _ => Err(Error::InvalidOption(arg)),
         };
         options.push(arg?);
@@ -244,12 +559,37 @@ pub fn process_args() -> Result<Vec<ProgramOption>, Error> {
     Ok(options)
 }
 
+pub fn default_cache_dir() -> std::path::PathBuf {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(std::path::PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    base.join(env!("CARGO_PKG_NAME"))
+}
+
+// Unlike the cache, the state directory holds data that cannot be rebuilt.
+pub fn default_state_dir() -> std::path::PathBuf {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(std::path::PathBuf::from))
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".local/state"))
+        })
+        .unwrap_or_else(std::env::temp_dir);
+    base.join(env!("CARGO_PKG_NAME"))
+}
+
 pub fn print_help() {
     println!("Usage: {} [OPTIONS] DIRECTORY", env!("CARGO_PKG_NAME"));
     println!("       {} --help", env!("CARGO_PKG_NAME"));
     println!("Options:");
     println!("\t --help");
     println!("\t --port=<u16>");
+    println!("\t --user=<name>:<password>");
+    println!("\t --cache-dir=<path>");
+    println!("\t --state-dir=<path>");
+    println!("\t --config=<path>");
 }
 
 #[derive(Debug)]
@@ -261,7 +601,43 @@ pub struct HashError {
 
 const BUF_SIZE: usize = 1024 * 1024;
 
-fn md5_hash(path: &std::path::Path) -> Result<Vec<u8>, HashError> {
+// Audio IDs fall back to the whole file for formats whose audio cannot be told apart.
+fn md5_hash(path: &std::path::Path, ids: IdMode) -> Result<Vec<u8>, HashError> {
+    let error = |e| HashError {
+        path: path.to_owned(),
+        error: e,
+    };
+    // A file that ends within its container structure is hashed as a whole.
+    let ranges = match ids {
+        IdMode::File => None,
+        IdMode::Audio => match payload::audio_ranges(path) {
+            Ok(ranges) => ranges,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => None,
+            Err(err) => return Err(error(err)),
+        },
+    };
+    let Some(ranges) = ranges else {
+        return md5_file(path);
+    };
+
+    let mut hasher = Md5::new();
+    let mut file = std::fs::File::open(path).map_err(error)?;
+    let mut buf: Vec<u8> = vec![0; BUF_SIZE];
+    for (start, end) in ranges {
+        file.seek(std::io::SeekFrom::Start(start)).map_err(error)?;
+        let mut remaining = end.saturating_sub(start);
+        while remaining > 0 {
+            let chunk = &mut buf[..remaining.min(BUF_SIZE as u64) as usize];
+            file.read_exact(chunk).map_err(error)?;
+            hasher.update(&chunk);
+            remaining -= chunk.len() as u64;
+        }
+    }
+
+    Ok(hasher.finalize().to_vec())
+}
+
+fn md5_file(path: &std::path::Path) -> Result<Vec<u8>, HashError> {
     let mut hasher = Md5::new();
 
     let mut file = std::fs::File::open(path).map_err(|e| HashError {
@@ -301,6 +677,16 @@ fn md5_hash(path: &std::path::Path) -> Result<Vec<u8>, HashError> {
     Ok(hash.to_vec())
 }
 
-fn hex_encode(hash: Vec<u8>) -> String {
+pub(crate) fn hex_encode(hash: Vec<u8>) -> String {
     hash.iter().map(|x| format!("{:02x}", x)).collect()
 }
+
+pub(crate) fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
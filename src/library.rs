@@ -0,0 +1,347 @@
+use crate::search::{SearchIndex, load_or_build};
+use crate::{AudioFiles, HashingCache, TrackTags, hex_encode, paths_by_hash};
+use md5::{Digest, Md5};
+
+pub struct Directory {
+    pub id: String,
+    pub parent: Option<String>,
+    pub name: String,
+    pub path: std::path::PathBuf,
+    pub directories: Vec<String>,
+    pub files: Vec<String>,
+}
+
+pub struct Artist {
+    pub id: String,
+    pub name: String,
+    pub albums: Vec<String>,
+}
+
+pub struct Album {
+    pub id: String,
+    pub name: String,
+    pub artist: String,
+    pub artist_id: String,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    pub duration: u64,
+    pub songs: Vec<String>,
+    pub cover_art: Option<String>,
+}
+
+pub struct Library {
+    pub base_dir: std::path::PathBuf,
+    pub audiofiles: AudioFiles,
+    pub root: String,
+    pub directories: std::collections::HashMap<String, Directory>,
+    pub file_parents: std::collections::HashMap<String, String>,
+    pub tracks: std::collections::HashMap<String, TrackTags>,
+    pub artists: std::collections::HashMap<String, Artist>,
+    pub albums: std::collections::HashMap<String, Album>,
+    pub track_albums: std::collections::HashMap<String, String>,
+    // Hashes found at more than one path, with all of their paths.
+    pub duplicates: std::collections::HashMap<String, Vec<std::path::PathBuf>>,
+    pub search: SearchIndex,
+    pub last_modified: std::time::SystemTime,
+}
+
+pub const MUSIC_FOLDER_ID: u32 = 1;
+pub const UNKNOWN_ARTIST: &str = "[Unknown Artist]";
+pub const UNKNOWN_ALBUM: &str = "[Unknown Album]";
+
+impl Library {
+    pub fn new(
+        base_dir: &str,
+        audiofiles: AudioFiles,
+        cache: &HashingCache,
+        cache_dir: &std::path::Path,
+    ) -> Library {
+        let base_dir = std::path::PathBuf::from(base_dir);
+        let root = directory_id(std::path::Path::new(""));
+        let mut directories = std::collections::HashMap::new();
+        directories.insert(
+            root.clone(),
+            Directory {
+                id: root.clone(),
+                parent: None,
+                name: base_dir
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| base_dir.to_string_lossy().to_string()),
+                path: base_dir.clone(),
+                directories: vec![],
+                files: vec![],
+            },
+        );
+
+        let mut file_parents = std::collections::HashMap::new();
+        for (hash, path) in audiofiles.iter() {
+            let Some(relative_dir) = path
+                .parent()
+                .and_then(|parent| parent.strip_prefix(&base_dir).ok())
+            else {
+                continue;
+            };
+
+            let mut parent_id = root.clone();
+            let mut relative_path = std::path::PathBuf::new();
+            for component in relative_dir.components() {
+                relative_path.push(component);
+                let id = directory_id(&relative_path);
+                if !directories.contains_key(&id) {
+                    directories.insert(
+                        id.clone(),
+                        Directory {
+                            id: id.clone(),
+                            parent: Some(parent_id.clone()),
+                            name: component.as_os_str().to_string_lossy().to_string(),
+                            path: base_dir.join(&relative_path),
+                            directories: vec![],
+                            files: vec![],
+                        },
+                    );
+                    if let Some(parent) = directories.get_mut(&parent_id) {
+                        parent.directories.push(id.clone());
+                    }
+                }
+                parent_id = id;
+            }
+
+            if let Some(parent) = directories.get_mut(&parent_id) {
+                parent.files.push(hash.clone());
+            }
+            file_parents.insert(hash.clone(), parent_id);
+        }
+
+        let names: std::collections::HashMap<String, String> = directories
+            .values()
+            .map(|dir| (dir.id.clone(), dir.name.to_lowercase()))
+            .collect();
+        for dir in directories.values_mut() {
+            dir.directories.sort_by_key(|id| names.get(id).cloned());
+            dir.files.sort_by_key(|hash| {
+                audiofiles
+                    .get(hash)
+                    .and_then(|path| path.file_name())
+                    .map(|name| name.to_string_lossy().to_lowercase())
+            });
+        }
+
+        let tracks: std::collections::HashMap<String, TrackTags> = audiofiles
+            .iter()
+            .map(|(hash, path)| {
+                let tags = cache.get(path).map(|c| c.tags.clone()).unwrap_or_default();
+                (hash.clone(), tags)
+            })
+            .collect();
+        let (artists, albums, track_albums) = group_albums(&audiofiles, &tracks);
+
+        let mut library = Library {
+            base_dir,
+            audiofiles,
+            root,
+            directories,
+            file_parents,
+            tracks,
+            artists,
+            albums,
+            track_albums,
+            duplicates: paths_by_hash(cache)
+                .into_iter()
+                .filter(|(_, paths)| paths.len() > 1)
+                .collect(),
+            search: SearchIndex::default(),
+            last_modified: std::time::SystemTime::now(),
+        };
+        library.search = load_or_build(&library, cache_dir);
+        library
+    }
+
+    pub fn cover_art_track(&self, id: &str) -> Option<String> {
+        let with_artwork = |hash: &&String| self.tracks.get(*hash).is_some_and(|t| t.has_artwork);
+
+        if self.tracks.contains_key(id) {
+            return Some(id.to_owned());
+        }
+        if let Some(album) = self.albums.get(id) {
+            return album.songs.iter().find(with_artwork).cloned();
+        }
+        if let Some(artist) = self.artists.get(id) {
+            return artist
+                .albums
+                .iter()
+                .find_map(|album| self.cover_art_track(album));
+        }
+        let mut dirs = std::collections::VecDeque::from([self.directories.get(id)?]);
+        while let Some(dir) = dirs.pop_front() {
+            if let Some(hash) = dir.files.iter().find(with_artwork) {
+                return Some(hash.clone());
+            }
+            dirs.extend(
+                dir.directories
+                    .iter()
+                    .filter_map(|id| self.directories.get(id)),
+            );
+        }
+
+        None
+    }
+
+    pub fn relative_path(&self, path: &std::path::Path) -> String {
+        path.strip_prefix(&self.base_dir)
+            .unwrap_or(path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+type Artists = std::collections::HashMap<String, Artist>;
+type Albums = std::collections::HashMap<String, Album>;
+
+// Artists and albums are grouped by their names regardless of case. Their IDs
+// derive from those lowercased names, so they survive tracks being added,
+// removed or retagged, and their names are the most common spelling with ties
+// going to the lowest track ID, so neither depends on the order in which
+// tracks are visited.
+fn group_albums(
+    audiofiles: &AudioFiles,
+    tracks: &std::collections::HashMap<String, TrackTags>,
+) -> (Artists, Albums, std::collections::HashMap<String, String>) {
+    type Group<'a> = Vec<(&'a String, &'a str)>;
+    let mut artist_groups: std::collections::HashMap<String, Group> =
+        std::collections::HashMap::new();
+    let mut album_groups: std::collections::HashMap<(String, String), Group> =
+        std::collections::HashMap::new();
+
+    let mut hashes: Vec<&String> = tracks.keys().collect();
+    hashes.sort();
+    for hash in hashes {
+        let tags = &tracks[hash];
+        let artist_name = tags
+            .album_artist
+            .as_deref()
+            .or(tags.artist.as_deref())
+            .unwrap_or(UNKNOWN_ARTIST);
+        let album_name = tags.album.as_deref().unwrap_or(UNKNOWN_ALBUM);
+        artist_groups
+            .entry(artist_name.to_lowercase())
+            .or_default()
+            .push((hash, artist_name));
+        album_groups
+            .entry((artist_name.to_lowercase(), album_name.to_lowercase()))
+            .or_default()
+            .push((hash, album_name));
+    }
+
+    let mut artists: Artists = std::collections::HashMap::new();
+    let mut artist_ids = std::collections::HashMap::new();
+    for (key, group) in &artist_groups {
+        let id = prefixed_id("ar-", &[key]);
+        artist_ids.insert(key.as_str(), id.clone());
+        artists.insert(
+            id.clone(),
+            Artist {
+                id,
+                name: display_name(group),
+                albums: vec![],
+            },
+        );
+    }
+
+    let mut albums: Albums = std::collections::HashMap::new();
+    let mut track_albums = std::collections::HashMap::new();
+    for ((artist_key, album_key), group) in &album_groups {
+        let id = prefixed_id("al-", &[artist_key, album_key]);
+        let artist_id = artist_ids[artist_key.as_str()].clone();
+        let artist = artists
+            .get_mut(&artist_id)
+            .expect("Infallible: every album artist was grouped");
+        artist.albums.push(id.clone());
+        let mut album = Album {
+            id: id.clone(),
+            name: display_name(group),
+            artist: artist.name.clone(),
+            artist_id,
+            year: None,
+            genre: None,
+            duration: 0,
+            songs: vec![],
+            cover_art: None,
+        };
+        for (hash, _) in group {
+            album.duration += tracks[*hash].duration;
+            album.songs.push((*hash).clone());
+            track_albums.insert((*hash).clone(), id.clone());
+        }
+        albums.insert(id, album);
+    }
+
+    for album in albums.values_mut() {
+        album.songs.sort_by_key(|hash| {
+            let tags = tracks.get(hash);
+            (
+                tags.and_then(|t| t.disc).unwrap_or(1),
+                tags.and_then(|t| t.track).unwrap_or(u32::MAX),
+                audiofiles
+                    .get(hash)
+                    .and_then(|p| p.file_name())
+                    .map(|n| n.to_owned()),
+            )
+        });
+        let songs = album.songs.iter().filter_map(|hash| tracks.get(hash));
+        album.year = songs.clone().find_map(|tags| tags.year);
+        album.genre = songs.clone().find_map(|tags| tags.genre.clone());
+        if songs.clone().any(|tags| tags.has_artwork) {
+            album.cover_art = Some(album.id.clone());
+        }
+    }
+    for artist in artists.values_mut() {
+        artist.albums.sort_by_key(|id| {
+            albums
+                .get(id)
+                .map(|album| (album.year, album.name.to_lowercase()))
+        });
+    }
+
+    (artists, albums, track_albums)
+}
+
+// Groups list their tracks in ID order, so the first spelling of a count wins a tie.
+fn display_name(group: &[(&String, &str)]) -> String {
+    let mut counts: Vec<(&str, usize)> = vec![];
+    for (_, name) in group {
+        match counts.iter_mut().find(|(spelling, _)| spelling == name) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((name, 1)),
+        }
+    }
+    let max = counts.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    counts
+        .into_iter()
+        .find(|(_, count)| *count == max)
+        .map(|(name, _)| name.to_owned())
+        .unwrap_or_default()
+}
+
+fn prefixed_id(prefix: &str, parts: &[&str]) -> String {
+    let mut hasher = Md5::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\0");
+    }
+    format!("{prefix}{}", hex_encode(hasher.finalize().to_vec()))
+}
+
+fn directory_id(relative_path: &std::path::Path) -> String {
+    let relative_path = relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let mut hasher = Md5::new();
+    hasher.update(b"dir:");
+    hasher.update(relative_path.as_bytes());
+    hex_encode(hasher.finalize().to_vec())
+}
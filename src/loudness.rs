@@ -0,0 +1,106 @@
+use lofty::file::{AudioFile, FileType, TaggedFile, TaggedFileExt};
+use lofty::tag::ItemKey;
+use std::io::Read;
+
+// Gains in dB relative to the ReplayGain reference of -18 LUFS, peaks as
+// linear sample amplitude where 1.0 is full scale.
+#[derive(Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+pub struct ReplayGain {
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+    // The output gain of an Opus header in dB, which decoders always apply.
+    pub header_gain: Option<f64>,
+}
+
+// R128 gains are relative to -23 LUFS.
+const R128_OFFSET: f64 = 5.0;
+
+pub fn read_replay_gain(path: &std::path::Path, tagged_file: &TaggedFile) -> ReplayGain {
+    let tags = tagged_file.tags();
+    let value = |key: ItemKey| {
+        tags.iter()
+            .find_map(|tag| tag.get_string(key))
+            .and_then(parse_value)
+    };
+    let mut replay_gain = ReplayGain {
+        track_gain: value(ItemKey::ReplayGainTrackGain),
+        track_peak: value(ItemKey::ReplayGainTrackPeak),
+        album_gain: value(ItemKey::ReplayGainAlbumGain),
+        album_peak: value(ItemKey::ReplayGainAlbumPeak),
+        header_gain: None,
+    };
+
+    if matches!(tagged_file.file_type(), FileType::Opus | FileType::Flac) {
+        let r128 = r128_gains(path, tagged_file.file_type());
+        replay_gain.track_gain = replay_gain.track_gain.or(r128.0);
+        replay_gain.album_gain = replay_gain.album_gain.or(r128.1);
+    }
+    if tagged_file.file_type() == FileType::Opus {
+        replay_gain.header_gain = opus_header_gain(path).filter(|gain| *gain != 0.0);
+    }
+    replay_gain
+}
+
+// Accepts "-6.54 dB", "-6.54" and "0.988"
+fn parse_value(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+}
+
+// R128_TRACK_GAIN and R128_ALBUM_GAIN are Q7.8 fixed point integers,
+// generic tags drop them, so they are read from the Vorbis comments.
+fn r128_gains(path: &std::path::Path, file_type: FileType) -> (Option<f64>, Option<f64>) {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return (None, None);
+    };
+    let options = lofty::config::ParseOptions::new().read_properties(false);
+    let comments = match file_type {
+        FileType::Opus => lofty::ogg::OpusFile::read_from(&mut file, options)
+            .ok()
+            .map(|opus| opus.vorbis_comments().clone()),
+        _ => lofty::flac::FlacFile::read_from(&mut file, options)
+            .ok()
+            .and_then(|flac| flac.vorbis_comments().cloned()),
+    };
+    let Some(comments) = comments else {
+        return (None, None);
+    };
+    let gain = |key: &str| {
+        comments
+            .get(key)
+            .and_then(|value| value.trim().parse::<i16>().ok())
+            .map(|value| value as f64 / 256.0 + R128_OFFSET)
+    };
+    (gain("R128_TRACK_GAIN"), gain("R128_ALBUM_GAIN"))
+}
+
+// The identification header is the only packet on the first Ogg page,
+// the gain is a little-endian Q7.8 value at offset 16 of it.
+fn opus_header_gain(path: &std::path::Path) -> Option<f64> {
+    let mut page = vec![];
+    std::fs::File::open(path)
+        .ok()?
+        .take(27 + 255 + 19)
+        .read_to_end(&mut page)
+        .ok()?;
+    if page.get(0..4)? != b"OggS" {
+        return None;
+    }
+    let segments = *page.get(26)? as usize;
+    let packet = page.get(27 + segments..)?;
+    if packet.get(0..8)? != b"OpusHead" {
+        return None;
+    }
+    let gain = i16::from_le_bytes(packet.get(16..18)?.try_into().ok()?);
+    Some(gain as f64 / 256.0)
+}
@@ -0,0 +1,287 @@
+use crate::id3;
+use lofty::file::TaggedFileExt;
+use lofty::tag::{ItemKey, TagType};
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LyricsSource {
+    // Sidecar files next to the track with the same name.
+    Lrc,
+    Txt,
+    // ID3v2 frames.
+    Sylt,
+    Uslt,
+    // Vorbis LYRICS, MP4 ©lyr and APE Lyrics items.
+    Tag,
+}
+
+// Start in milliseconds, None for unsynced lyrics.
+#[derive(Clone, serde::Serialize)]
+pub struct LyricsLine {
+    pub start: Option<u64>,
+    pub text: String,
+}
+
+#[derive(Clone, serde::Serialize)]
+pub struct Lyrics {
+    pub source: LyricsSource,
+    // ISO 639 code when the source declares one.
+    pub language: Option<String>,
+    pub description: Option<String>,
+    pub synced: bool,
+    pub lines: Vec<LyricsLine>,
+}
+
+// Sidecar files change the lyrics without changing the track, so validators of
+// responses that depend on them include their size and modification time.
+pub fn sidecar_version(path: &std::path::Path) -> String {
+    ["lrc", "txt"]
+        .iter()
+        .map(|ext| {
+            let Ok(metadata) = std::fs::metadata(path.with_extension(ext)) else {
+                return "0".to_owned();
+            };
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(0, |duration| duration.as_nanos());
+            format!("{:x}.{modified:x}", metadata.len())
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// Synced lyrics come before unsynced ones, sidecar files before embedded lyrics.
+// Unsynced text that turns out to be LRC is returned as synced.
+pub fn read_lyrics(path: &std::path::Path) -> Vec<Lyrics> {
+    let mut lyrics = vec![];
+    if let Ok(text) = std::fs::read(path.with_extension("lrc")) {
+        lyrics.extend(from_text(LyricsSource::Lrc, None, None, &decode(&text)));
+    }
+
+    let id3_tag = std::fs::File::open(path)
+        .ok()
+        .and_then(|mut file| id3::Tag::read(&mut file));
+    if let Some(tag) = &id3_tag {
+        for (id, frame) in tag.frames() {
+            match id {
+                b"SYLT" => lyrics.extend(id3_synced(frame)),
+                b"USLT" => lyrics.extend(id3_unsynced(frame)),
+                _ => {}
+            }
+        }
+    }
+    if let Ok(tagged_file) = lofty::read_from_path(path) {
+        for tag in tagged_file.tags() {
+            // ID3v2 lyrics were read with their language above.
+            if tag.tag_type() == TagType::Id3v2 && id3_tag.is_some() {
+                continue;
+            }
+            for text in tag
+                .get_strings(ItemKey::Lyrics)
+                .chain(tag.get_strings(ItemKey::UnsyncLyrics))
+            {
+                lyrics.extend(from_text(LyricsSource::Tag, None, None, text));
+            }
+        }
+    }
+
+    if let Ok(text) = std::fs::read(path.with_extension("txt")) {
+        lyrics.extend(from_text(LyricsSource::Txt, None, None, &decode(&text)));
+    }
+
+    let mut unique: Vec<Lyrics> = vec![];
+    for entry in lyrics {
+        let duplicate = unique.iter().any(|other| {
+            other.synced == entry.synced
+                && other.lines.len() == entry.lines.len()
+                && other
+                    .lines
+                    .iter()
+                    .zip(&entry.lines)
+                    .all(|(a, b)| a.start == b.start && a.text == b.text)
+        });
+        if !duplicate {
+            unique.push(entry);
+        }
+    }
+    unique.sort_by_key(|lyrics| !lyrics.synced);
+    unique
+}
+
+fn decode(data: &[u8]) -> String {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    String::from_utf8_lossy(data).to_string()
+}
+
+fn from_text(
+    source: LyricsSource,
+    language: Option<String>,
+    description: Option<String>,
+    text: &str,
+) -> Option<Lyrics> {
+    let (lrc_language, synced) = parse_lrc(text);
+    if !synced.is_empty() {
+        return Some(Lyrics {
+            source,
+            language: language.or(lrc_language),
+            description,
+            synced: true,
+            lines: synced,
+        });
+    }
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    let first = lines.iter().position(|line| !line.is_empty())?;
+    let last = lines.iter().rposition(|line| !line.is_empty())?;
+    Some(Lyrics {
+        source,
+        language,
+        description,
+        synced: false,
+        lines: lines[first..=last]
+            .iter()
+            .map(|line| LyricsLine {
+                start: None,
+                text: line.to_string(),
+            })
+            .collect(),
+    })
+}
+
+// Reads `[mm:ss.xx]text` lines, where a line may carry several timestamps,
+// the `[offset:ms]` header shifts every line and `[la:code]` sets the language.
+// Word timestamps of enhanced LRC are dropped.
+fn parse_lrc(text: &str) -> (Option<String>, Vec<LyricsLine>) {
+    let mut language = None;
+    let mut offset: i64 = 0;
+    let mut timed: Vec<(i64, String)> = vec![];
+    for line in text.lines() {
+        let mut rest = line.trim();
+        let mut starts = vec![];
+        while let Some(tag) = rest.strip_prefix('[') {
+            let Some(end) = tag.find(']') else {
+                break;
+            };
+            let (content, after) = (&tag[..end], &tag[end + 1..]);
+            match timestamp(content) {
+                Some(start) => starts.push(start),
+                None => match content.split_once(':') {
+                    Some(("offset", value)) => offset = value.trim().parse().unwrap_or(0),
+                    Some(("la" | "lang", value)) if !value.trim().is_empty() => {
+                        language = Some(value.trim().to_lowercase())
+                    }
+                    _ => {}
+                },
+            }
+            rest = after.trim_start();
+        }
+        let text = strip_word_timestamps(rest);
+        timed.extend(starts.into_iter().map(|start| (start, text.clone())));
+    }
+
+    // A positive offset makes the lyrics appear sooner.
+    timed.sort_by_key(|(start, _)| *start);
+    let lines = timed
+        .into_iter()
+        .map(|(start, text)| LyricsLine {
+            start: Some((start - offset).max(0) as u64),
+            text,
+        })
+        .collect();
+    (language, lines)
+}
+
+// Accepts mm:ss, mm:ss.x to mm:ss.xxx and mm:ss:xx.
+fn timestamp(tag: &str) -> Option<i64> {
+    let (minutes, seconds) = tag.split_once(':')?;
+    let (seconds, fraction) = match seconds.split_once(['.', ':']) {
+        Some((seconds, fraction)) => (seconds, fraction),
+        None => (seconds, ""),
+    };
+    let digits = |value: &str| !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit());
+    if !digits(minutes) || !digits(seconds) || !(fraction.is_empty() || digits(fraction)) {
+        return None;
+    }
+    let fraction = fraction.get(..3).unwrap_or(fraction);
+    let millis = match fraction.len() {
+        0 => 0,
+        length => fraction.parse::<i64>().ok()? * 10i64.pow(3 - length as u32),
+    };
+    Some((minutes.parse::<i64>().ok()? * 60 + seconds.parse::<i64>().ok()?) * 1000 + millis)
+}
+
+fn strip_word_timestamps(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        result.push_str(&rest[..start]);
+        match rest[start..].find('>') {
+            Some(end) if timestamp(&rest[start + 1..start + end]).is_some() => {
+                rest = &rest[start + end + 1..];
+            }
+            _ => {
+                result.push('<');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result.trim().to_string()
+}
+
+// ISO 639-2 codes, "XXX" and "und" mean the language is unknown.
+fn id3_language(code: &[u8]) -> Option<String> {
+    let code = String::from_utf8_lossy(code).trim().to_lowercase();
+    let known = code.len() == 3
+        && code.bytes().all(|b| b.is_ascii_lowercase())
+        && !matches!(code.as_str(), "xxx" | "und");
+    known.then_some(code)
+}
+
+fn non_empty(text: String) -> Option<String> {
+    let text = text.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+fn id3_unsynced(frame: &[u8]) -> Option<Lyrics> {
+    let (encoding, data) = frame.split_first()?;
+    let language = id3_language(data.get(0..3)?);
+    let (description, data) = id3::string(*encoding, data.get(3..)?);
+    let (text, _) = id3::string(*encoding, data);
+    from_text(LyricsSource::Uslt, language, non_empty(description), &text)
+}
+
+// Only frames with millisecond timestamps holding lyrics are read,
+// MPEG frame timestamps depend on the stream and are rare.
+fn id3_synced(frame: &[u8]) -> Option<Lyrics> {
+    let (encoding, data) = frame.split_first()?;
+    let language = id3_language(data.get(0..3)?);
+    let (timestamp_format, content_type) = (*data.get(3)?, *data.get(4)?);
+    if timestamp_format != 2 || content_type != 1 {
+        return None;
+    }
+    let (description, mut data) = id3::string(*encoding, data.get(5..)?);
+
+    let mut lines = vec![];
+    while !data.is_empty() {
+        let (text, rest) = id3::string(*encoding, data);
+        let start = u32::from_be_bytes(rest.get(0..4)?.try_into().ok()?);
+        lines.push(LyricsLine {
+            start: Some(start as u64),
+            text: text.trim().to_string(),
+        });
+        data = &rest[4..];
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    lines.sort_by_key(|line| line.start);
+    Some(Lyrics {
+        source: LyricsSource::Sylt,
+        language,
+        description: non_empty(description),
+        synced: true,
+        lines,
+    })
+}
@@ -1,9 +1,27 @@
-use actix_web::{App, HttpServer, middleware::Logger, web};
-use std::sync::Mutex;
+use actix_web::middleware::{Logger, from_fn};
+use actix_web::{App, HttpServer, web};
+use std::sync::{Arc, Mutex, RwLock};
+use subsonic_vault::analysis::{LoudnessStore, start_analysis};
+use subsonic_vault::bookmarks::Bookmarks;
+use subsonic_vault::hls::SegmentTables;
+use subsonic_vault::ids::{Aliases, redirect_aliases};
+use subsonic_vault::index;
+use subsonic_vault::library::Library;
+use subsonic_vault::scan::ScanProgress;
 use subsonic_vault::services::{
-    get_file_artwork_by_id, get_file_by_id, get_file_metadata_by_id, get_files, home, ping, scan,
+    delete_bookmark, get_aliases, get_bookmarks, get_duplicates, get_file_artwork_by_id,
+    get_file_by_id, get_file_chapter_image, get_file_chapters_by_id, get_file_hls_playlist,
+    get_file_hls_segment, get_file_lyrics_by_id, get_file_metadata_by_id, get_files,
+    get_play_queue, get_random_files, get_scan_status, home, ping, save_bookmark, save_play_queue,
+    scan, search,
+};
+use subsonic_vault::settings::Settings;
+use subsonic_vault::transcode_cache::TranscodeCache;
+use subsonic_vault::watch::start_watcher;
+use subsonic_vault::{
+    AppState, HashingCache, ProgramOption, default_cache_dir, default_state_dir, print_help,
+    process_args, subsonic, traverse_dir,
 };
-use subsonic_vault::{AppState, ProgramOption, print_help, process_args, traverse_dir};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -26,14 +44,13 @@ async fn main() -> std::io::Result<()> {
         std::process::exit(-1);
     }
 
-    let port = if let Some(port) = options.iter().find_map(|o| match o {
-        ProgramOption::Port(p) => Some(*p),
-        _ => None,
-    }) {
-        port
-    } else {
-        65421
-    };
+    let port = options
+        .iter()
+        .find_map(|o| match o {
+            ProgramOption::Port(p) => Some(*p),
+            _ => None,
+        })
+        .unwrap_or(65421);
 
     let base_dir = options
         .iter()
@@ -43,23 +60,124 @@ async fn main() -> std::io::Result<()> {
         })
         .unwrap();
 
-    let cache = std::collections::HashMap::new();
-    let (audiofiles, cache) = traverse_dir(&base_dir, cache).unwrap();
+    let users: std::collections::HashMap<String, String> = options
+        .iter()
+        .filter_map(|o| match o {
+            ProgramOption::User(name, password) => Some((name.clone(), password.clone())),
+            _ => None,
+        })
+        .collect();
+    if users.is_empty() {
+        eprintln!("No --user configured, the Subsonic API accepts requests without authentication");
+    }
+
+    let cache_dir = options
+        .iter()
+        .find_map(|o| match o {
+            ProgramOption::CacheDir(path) => Some(path.clone()),
+            _ => None,
+        })
+        .unwrap_or_else(default_cache_dir);
+
+    let state_dir = options
+        .iter()
+        .find_map(|o| match o {
+            ProgramOption::StateDir(path) => Some(path.clone()),
+            _ => None,
+        })
+        .unwrap_or_else(default_state_dir);
+
+    let settings = options
+        .iter()
+        .find_map(|o| match o {
+            ProgramOption::Config(path) => Some(Settings::load(path).unwrap_or_else(|err| {
+                eprintln!("Failed to load the config file {path:?}: {err:?}");
+                std::process::exit(-1);
+            })),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let ids = settings.library.ids;
+    let index = index::load(&state_dir);
+    let (cache, previous) = match index.ids == ids {
+        true => (index.cache, HashingCache::new()),
+        false => (HashingCache::new(), index.cache),
+    };
+    let (audiofiles, cache) =
+        traverse_dir(&base_dir, cache, ids, &ScanProgress::default()).unwrap();
+    let bookmarks = Bookmarks::load(&state_dir);
+    let loudness = LoudnessStore::load(&state_dir);
+    let aliases = Aliases::load(&state_dir);
+    // The index keeps the previous IDs until the references are migrated,
+    // an interrupted migration is repeated on the next start.
+    if !previous.is_empty() {
+        match aliases.migrate(&previous, &cache) {
+            Ok(mapping) => {
+                println!("Mapped {} file IDs to the {ids:?} ID mode", mapping.len());
+                if let Err(err) = bookmarks.migrate(&mapping) {
+                    eprintln!("Failed to migrate the bookmarks: {err:?}");
+                }
+                if let Err(err) = loudness.migrate(&mapping) {
+                    eprintln!("Failed to migrate the loudness measurements: {err:?}");
+                }
+            }
+            Err(err) => eprintln!("Failed to save the ID aliases: {err:?}"),
+        }
+    }
+    if let Err(err) = index::save(&state_dir, ids, &cache) {
+        eprintln!("Failed to save the library index: {err:?}");
+    }
+    let library = Library::new(&base_dir, audiofiles, &cache, &cache_dir);
+    let transcode_cache = TranscodeCache::new(
+        cache_dir.join("transcodes"),
+        settings.transcoding.cache_size * 1024 * 1024,
+    );
+    let data = web::Data::new(AppState {
+        base_dir,
+        cache_dir,
+        state_dir,
+        library: RwLock::new(Arc::new(library)),
+        hashing_cache: Mutex::new(cache),
+        users,
+        scan_progress: ScanProgress::default(),
+        settings,
+        transcode_cache,
+        bookmarks,
+        loudness,
+        aliases,
+        hls_segments: SegmentTables::default(),
+    });
+    start_analysis(data.clone());
+    start_watcher(data.clone());
     HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(AppState {
-                base_dir: base_dir.clone(),
-                audiofiles: Mutex::new(audiofiles.clone()),
-                hashing_cache: Mutex::new(cache.clone()),
-            }))
+            .app_data(data.clone())
+            .wrap(from_fn(redirect_aliases))
             .wrap(Logger::default())
             .service(home)
             .service(scan)
+            .service(get_scan_status)
             .service(get_files)
+            .service(search)
+            .service(get_duplicates)
+            .service(get_aliases)
+            .service(get_random_files)
             .service(get_file_by_id)
             .service(get_file_metadata_by_id)
             .service(get_file_artwork_by_id)
+            .service(get_file_chapters_by_id)
+            .service(get_file_chapter_image)
+            .service(get_file_lyrics_by_id)
+            .service(get_file_hls_playlist)
+            .service(get_file_hls_segment)
+            .service(get_bookmarks)
+            .service(save_bookmark)
+            .service(delete_bookmark)
+            .service(get_play_queue)
+            .service(save_play_queue)
             .service(ping)
+            .service(subsonic::scope())
             .service(actix_files::Files::new("/player", "./player/dist").index_file("index.html"))
             .service(actix_files::Files::new("/assets", "./player/dist/assets"))
     })
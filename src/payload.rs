@@ -0,0 +1,267 @@
+use std::io::{Read, Seek, SeekFrom};
+
+// Byte ranges (start, end) of the encoded audio, leaving out tags, artwork and
+// container headers that change when a file is retagged. None for formats
+// that are not understood, which are then identified by the whole file.
+pub fn audio_ranges(path: &std::path::Path) -> std::io::Result<Option<Vec<(u64, u64)>>> {
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let len = file.get_ref().metadata()?.len();
+    let mut magic = [0u8; 12];
+    let start = id3v2_end(&mut file)?;
+    file.seek(SeekFrom::Start(start))?;
+    if len.saturating_sub(start) < 12 {
+        return Ok(None);
+    }
+    file.read_exact(&mut magic)?;
+
+    let ranges = match &magic {
+        [b'f', b'L', b'a', b'C', ..] => {
+            let end = trailing_tags_start(&mut file, len)?;
+            flac_frames(&mut file, start, end)?
+        }
+        [b'O', b'g', b'g', b'S', ..] => ogg_audio_pages(&mut file, start, len)?,
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E'] => {
+            riff_data(&mut file, start, len)?
+        }
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => mp4_mdat(&mut file, start, len)?,
+        [0xff, second, ..] if second & 0xe0 == 0xe0 => {
+            Some(vec![(start, trailing_tags_start(&mut file, len)?)])
+        }
+        _ => None,
+    };
+    Ok(ranges.filter(|ranges| !ranges.is_empty()))
+}
+
+// Skips ID3v2 tags at the start, several of them may follow each other.
+fn id3v2_end(file: &mut (impl Read + Seek)) -> std::io::Result<u64> {
+    let mut offset = 0;
+    loop {
+        let mut header = [0u8; 10];
+        file.seek(SeekFrom::Start(offset))?;
+        if file.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" {
+            return Ok(offset);
+        }
+        let size = header[6..10]
+            .iter()
+            .fold(0u64, |size, byte| (size << 7) | (*byte & 0x7f) as u64);
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        offset += 10 + size + footer;
+    }
+}
+
+// ID3v1, APEv2 and appended ID3v2 tags at the end, in any order.
+fn trailing_tags_start(file: &mut (impl Read + Seek), len: u64) -> std::io::Result<u64> {
+    let mut end = len;
+    loop {
+        let mut tail = [0u8; 32];
+        if end < 128 {
+            return Ok(end);
+        }
+        file.seek(SeekFrom::Start(end - 128))?;
+        file.read_exact(&mut tail[..3])?;
+        if &tail[..3] == b"TAG" {
+            end -= 128;
+            continue;
+        }
+        file.seek(SeekFrom::Start(end - 32))?;
+        file.read_exact(&mut tail)?;
+        if &tail[..8] == b"APETAGEX" {
+            let size = u32::from_le_bytes([tail[12], tail[13], tail[14], tail[15]]) as u64;
+            // The size includes the footer itself, anything less would never move `end`.
+            if size < 32 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let header = if tail[23] & 0x80 != 0 { 32 } else { 0 };
+            end = end.saturating_sub(size + header);
+            continue;
+        }
+        if &tail[22..25] == b"3DI" {
+            let size = tail[28..32]
+                .iter()
+                .fold(0u64, |size, byte| (size << 7) | (*byte & 0x7f) as u64);
+            end = end.saturating_sub(size + 20);
+            continue;
+        }
+        return Ok(end);
+    }
+}
+
+// Frames follow the last metadata block, which carries the flag 0x80.
+fn flac_frames(
+    file: &mut (impl Read + Seek),
+    start: u64,
+    end: u64,
+) -> std::io::Result<Option<Vec<(u64, u64)>>> {
+    let mut offset = start + 4;
+    loop {
+        let mut header = [0u8; 4];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        offset += 4 + length;
+        if header[0] & 0x80 != 0 {
+            return Ok((offset < end).then(|| vec![(offset, end)]));
+        }
+    }
+}
+
+fn riff_data(
+    file: &mut (impl Read + Seek),
+    start: u64,
+    len: u64,
+) -> std::io::Result<Option<Vec<(u64, u64)>>> {
+    let mut ranges = vec![];
+    let mut offset = start + 12;
+    while offset + 8 <= len {
+        let mut header = [0u8; 8];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as u64;
+        if &header[0..4] == b"data" {
+            ranges.push((offset + 8, (offset + 8 + size).min(len)));
+        }
+        offset += 8 + size + size % 2;
+    }
+    Ok(Some(ranges))
+}
+
+// Top-level `mdat` atoms, metadata lives in `moov` and is rewritten on retagging.
+fn mp4_mdat(
+    file: &mut (impl Read + Seek),
+    start: u64,
+    len: u64,
+) -> std::io::Result<Option<Vec<(u64, u64)>>> {
+    let mut ranges = vec![];
+    let mut offset = start;
+    while offset + 8 <= len {
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header[..8])?;
+        let (size, header_size) =
+            match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+                0 => (len - offset, 8),
+                1 => {
+                    file.read_exact(&mut header[8..])?;
+                    (
+                        u64::from_be_bytes(header[8..16].try_into().unwrap_or_default()),
+                        16,
+                    )
+                }
+                size => (size as u64, 8),
+            };
+        let Some(next) = offset.checked_add(size).filter(|_| size >= header_size) else {
+            return Ok(None);
+        };
+        if &header[4..8] == b"mdat" {
+            ranges.push((offset + header_size, next.min(len)));
+        }
+        offset = next;
+    }
+    Ok(Some(ranges))
+}
+
+// Header packets, including the comments, end on a page boundary in every
+// mapping, so a page either belongs to the headers or holds audio packets.
+// Only the packet data of audio pages is hashed, page sequence numbers and
+// checksums shift when the comment header grows.
+fn ogg_audio_pages(
+    file: &mut (impl Read + Seek),
+    start: u64,
+    len: u64,
+) -> std::io::Result<Option<Vec<(u64, u64)>>> {
+    let mut ranges = vec![];
+    let mut headers = std::collections::HashMap::<u32, usize>::new();
+    let mut offset = start;
+    while offset + 27 <= len {
+        let mut header = [0u8; 27];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        if &header[0..4] != b"OggS" {
+            return Ok(None);
+        }
+        let serial = u32::from_le_bytes([header[14], header[15], header[16], header[17]]);
+        let mut lacing = vec![0u8; header[26] as usize];
+        file.read_exact(&mut lacing)?;
+        let data_start = offset + 27 + lacing.len() as u64;
+        let data_len: u64 = lacing.iter().map(|value| *value as u64).sum();
+
+        if header[5] & 0x02 != 0 {
+            let mut packet = [0u8; 9];
+            let available = data_len.min(9) as usize;
+            file.read_exact(&mut packet[..available])?;
+            let Some(count) = header_packets(&packet[..available]) else {
+                return Ok(None);
+            };
+            headers.insert(serial, count);
+        }
+        let remaining = headers.entry(serial).or_default();
+        if *remaining > 0 {
+            let completed = lacing.iter().filter(|value| **value < 255).count();
+            *remaining = remaining.saturating_sub(completed);
+        } else if data_len > 0 {
+            ranges.push((data_start, (data_start + data_len).min(len)));
+        }
+        offset = data_start + data_len;
+    }
+    Ok(Some(ranges))
+}
+
+// The number of header packets of a logical stream from its first packet.
+fn header_packets(packet: &[u8]) -> Option<usize> {
+    match packet {
+        [0x01, b'v', b'o', b'r', b'b', b'i', b's', ..] => Some(3),
+        [b'O', b'p', b'u', b's', b'H', b'e', b'a', b'd', ..] => Some(2),
+        [0x7f, b'F', b'L', b'A', b'C', _, _, high, low] => {
+            Some(1 + u16::from_be_bytes([*high, *low]) as usize)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    // Silent MPEG-1 Layer III frames at 128 kbit/s and 44.1 kHz.
+    fn mp3_frames(count: usize) -> Vec<u8> {
+        (0..count)
+            .flat_map(|_| [vec![0xff, 0xfb, 0x90, 0x00], vec![0; 413]].concat())
+            .collect()
+    }
+
+    fn ape_footer(size: u32, flags: u32) -> Vec<u8> {
+        let mut footer = b"APETAGEX".to_vec();
+        footer.extend_from_slice(&2000u32.to_le_bytes());
+        footer.extend_from_slice(&size.to_le_bytes());
+        footer.extend_from_slice(&0u32.to_le_bytes());
+        footer.extend_from_slice(&flags.to_le_bytes());
+        footer.extend_from_slice(&[0; 8]);
+        footer
+    }
+
+    #[test]
+    fn skips_trailing_ape_and_id3v1_tags() {
+        let dir = TestDir::new("ape-tags");
+        let path = dir.join("track.mp3");
+        let audio = mp3_frames(4);
+        let mut items = b"\x05\0\0\0\0\0\0\0Title\0Hello".to_vec();
+        items.extend_from_slice(&ape_footer(32 + items.len() as u32, 0));
+        let mut id3v1 = b"TAG".to_vec();
+        id3v1.resize(128, 0);
+        std::fs::write(&path, [audio.clone(), items, id3v1].concat()).unwrap();
+
+        let ranges = audio_ranges(&path).unwrap();
+        assert_eq!(ranges, Some(vec![(0, audio.len() as u64)]));
+    }
+
+    #[test]
+    fn rejects_ape_footers_that_cover_nothing() {
+        let dir = TestDir::new("ape-zero");
+        let path = dir.join("track.mp3");
+        std::fs::write(&path, [mp3_frames(4), ape_footer(0, 0)].concat()).unwrap();
+
+        let err = audio_ranges(&path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
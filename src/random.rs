@@ -0,0 +1,58 @@
+use crate::library::{Library, MUSIC_FOLDER_ID};
+use rand::seq::{IteratorRandom, SliceRandom};
+
+#[derive(Default, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RandomFilter {
+    pub size: Option<usize>,
+    pub genre: Option<String>,
+    pub from_year: Option<u32>,
+    pub to_year: Option<u32>,
+    pub music_folder_id: Option<u32>,
+}
+
+pub const DEFAULT_SIZE: usize = 10;
+pub const MAX_SIZE: usize = 500;
+
+impl RandomFilter {
+    pub fn matches(&self, library: &Library, hash: &str) -> bool {
+        if self.music_folder_id.is_some_and(|id| id != MUSIC_FOLDER_ID) {
+            return false;
+        }
+        let Some(tags) = library.tracks.get(hash) else {
+            return false;
+        };
+        if let Some(genre) = &self.genre
+            && !tags
+                .genre
+                .as_ref()
+                .is_some_and(|g| g.eq_ignore_ascii_case(genre))
+        {
+            return false;
+        }
+        if self.from_year.is_some() || self.to_year.is_some() {
+            let Some(year) = tags.year else {
+                return false;
+            };
+            if self.from_year.is_some_and(|from| year < from)
+                || self.to_year.is_some_and(|to| year > to)
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub fn random_songs(library: &Library, filter: &RandomFilter) -> Vec<String> {
+    let size = filter.size.unwrap_or(DEFAULT_SIZE).min(MAX_SIZE);
+    let mut rng = rand::rng();
+    let mut songs = library
+        .tracks
+        .keys()
+        .filter(|hash| filter.matches(library, hash))
+        .cloned()
+        .choose_multiple(&mut rng, size);
+    songs.shuffle(&mut rng);
+    songs
+}
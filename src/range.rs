@@ -0,0 +1,301 @@
+use crate::conditional::{Validators, header_str, unix_secs};
+use crate::seek::Seek;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, HttpDate};
+use actix_web::web::{self, Bytes};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use futures_util::FutureExt;
+use futures_util::future::LocalBoxFuture;
+use std::collections::VecDeque;
+use std::io::{Read, Seek as IoSeek, SeekFrom};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+
+pub const CHUNK_SIZE: u64 = 64 * 1024;
+
+// The bytes served for a file: an optional generated prefix followed by
+// the file contents from `offset`, which is how seeked streams are built.
+pub struct Source {
+    pub path: std::path::PathBuf,
+    pub prefix: Vec<u8>,
+    pub offset: u64,
+}
+
+impl Source {
+    pub fn new(path: &std::path::Path) -> Source {
+        Source {
+            path: path.to_owned(),
+            prefix: vec![],
+            offset: 0,
+        }
+    }
+
+    pub fn seeked(path: &std::path::Path, seek: Option<Seek>) -> Source {
+        match seek {
+            Some(seek) => Source {
+                path: path.to_owned(),
+                prefix: seek.prefix,
+                offset: seek.offset,
+            },
+            None => Source::new(path),
+        }
+    }
+
+    fn slice(&self, start: u64, end: u64, parts: &mut VecDeque<Part>) {
+        let prefix_len = self.prefix.len() as u64;
+        if start < prefix_len {
+            parts.push_back(Part::Bytes(Bytes::copy_from_slice(
+                &self.prefix[start as usize..end.min(prefix_len) as usize],
+            )));
+        }
+        if end > prefix_len {
+            let file_start = start.max(prefix_len);
+            parts.push_back(Part::File {
+                offset: self.offset + file_start - prefix_len,
+                len: end - file_start,
+            });
+        }
+    }
+}
+
+enum Part {
+    Bytes(Bytes),
+    File { offset: u64, len: u64 },
+}
+
+// Streams the selected parts from disk chunk by chunk with a known length,
+// so large files are never buffered in memory.
+pub struct RangeBody {
+    // Lent to the pending read while a chunk is being read.
+    file: Option<std::fs::File>,
+    parts: VecDeque<Part>,
+    size: u64,
+    reading: Option<LocalBoxFuture<'static, std::io::Result<(std::fs::File, Bytes)>>>,
+}
+
+impl MessageBody for RangeBody {
+    type Error = std::io::Error;
+
+    fn size(&self) -> BodySize {
+        BodySize::Sized(self.size)
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let reading = match &mut this.reading {
+            Some(reading) => reading,
+            None => match this.parts.pop_front() {
+                None => return Poll::Ready(None),
+                Some(Part::Bytes(bytes)) => return Poll::Ready(Some(Ok(bytes))),
+                Some(Part::File { offset, len }) => {
+                    let chunk_len = len.min(CHUNK_SIZE);
+                    if len > chunk_len {
+                        this.parts.push_front(Part::File {
+                            offset: offset + chunk_len,
+                            len: len - chunk_len,
+                        });
+                    }
+                    // The file is only gone after a failed read, which ends the body.
+                    let Some(file) = this.file.take() else {
+                        return Poll::Ready(None);
+                    };
+                    this.reading
+                        .insert(read_file_chunk(file, offset, chunk_len).boxed_local())
+                }
+            },
+        };
+        let read = std::task::ready!(reading.as_mut().poll(cx));
+        this.reading = None;
+        Poll::Ready(Some(match read {
+            Ok((file, chunk)) => {
+                this.file = Some(file);
+                Ok(chunk)
+            }
+            Err(error) => {
+                this.parts.clear();
+                Err(error)
+            }
+        }))
+    }
+}
+
+fn read_chunk(file: &mut std::fs::File, offset: u64, len: u64) -> std::io::Result<Bytes> {
+    let mut buffer = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buffer)?;
+    Ok(Bytes::from(buffer))
+}
+
+// Reads a chunk on the blocking thread pool so a slow disk never stalls the
+// worker, handing the file back for the next read.
+pub async fn read_file_chunk(
+    mut file: std::fs::File,
+    offset: u64,
+    len: u64,
+) -> std::io::Result<(std::fs::File, Bytes)> {
+    web::block(move || read_chunk(&mut file, offset, len).map(|chunk| (file, chunk)))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+// Answers with the whole source, a single range or multipart/byteranges
+// depending on the `Range` and `If-Range` headers of the request.
+pub fn respond(
+    req: &HttpRequest,
+    mut response: HttpResponseBuilder,
+    source: Source,
+    mime: &str,
+    validators: &Validators,
+) -> std::io::Result<HttpResponse> {
+    let file = std::fs::File::open(&source.path)?;
+    let total = source.prefix.len() as u64 + file.metadata()?.len().saturating_sub(source.offset);
+    response.insert_header((header::ACCEPT_RANGES, "bytes"));
+
+    let ranges = header_str(req, header::RANGE)
+        .filter(|_| if_range_matches(req, validators))
+        .and_then(|value| parse_ranges(value, total));
+
+    let mut parts = VecDeque::new();
+    let size = match ranges.as_deref() {
+        None => {
+            source.slice(0, total, &mut parts);
+            response.content_type(mime);
+            total
+        }
+        Some([]) => {
+            return Ok(response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{total}")))
+                .finish());
+        }
+        Some([(start, end)]) => {
+            source.slice(*start, *end, &mut parts);
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .content_type(mime)
+                .insert_header((
+                    header::CONTENT_RANGE,
+                    format!("bytes {start}-{}/{total}", end - 1),
+                ));
+            end - start
+        }
+        Some(ranges) => {
+            let boundary = format!("{:016x}", rand::random::<u64>());
+            let mut size = 0;
+            for (start, end) in ranges {
+                let part_header = format!(
+                    "\r\n--{boundary}\r\nContent-Type: {mime}\r\nContent-Range: bytes {start}-{}/{total}\r\n\r\n",
+                    end - 1
+                );
+                size += part_header.len() as u64 + end - start;
+                parts.push_back(Part::Bytes(Bytes::from(part_header)));
+                source.slice(*start, *end, &mut parts);
+            }
+            let closing = format!("\r\n--{boundary}--\r\n");
+            size += closing.len() as u64;
+            parts.push_back(Part::Bytes(Bytes::from(closing)));
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .content_type(format!("multipart/byteranges; boundary={boundary}"));
+            size
+        }
+    };
+
+    Ok(response.body(RangeBody {
+        file: Some(file),
+        parts,
+        size,
+        reading: None,
+    }))
+}
+
+// A stale `If-Range` validator means the client's partial copy is outdated,
+// so the whole representation is sent instead of the requested ranges.
+fn if_range_matches(req: &HttpRequest, validators: &Validators) -> bool {
+    let Some(value) = header_str(req, header::IF_RANGE) else {
+        return true;
+    };
+    if value.starts_with('"') {
+        return value == validators.quoted_etag();
+    }
+    match (HttpDate::from_str(value), validators.last_modified) {
+        (Ok(date), Some(modified)) => {
+            unix_secs(date.into()).is_some_and(|secs| Some(secs) == unix_secs(modified))
+        }
+        _ => false,
+    }
+}
+
+pub enum RangeRequest {
+    Bounded(u64, u64),
+    From(u64),
+    Other,
+}
+
+// The requested range for sources whose length isn't known yet,
+// suffix and multiple ranges need the length and are reported as Other.
+pub fn requested(req: &HttpRequest) -> Option<RangeRequest> {
+    let specs = header_str(req, header::RANGE)?
+        .trim()
+        .strip_prefix("bytes=")?;
+    let Some((first, last)) = specs.split_once('-').filter(|_| !specs.contains(',')) else {
+        return Some(RangeRequest::Other);
+    };
+    match (first.trim().parse::<u64>(), last.trim()) {
+        (Ok(first), "") => Some(RangeRequest::From(first)),
+        (Ok(first), last) => match last.parse::<u64>() {
+            Ok(last) if last >= first => Some(RangeRequest::Bounded(first, last)),
+            _ => None,
+        },
+        _ => Some(RangeRequest::Other),
+    }
+}
+
+// Returns None for headers that have to be ignored, and an empty list
+// when none of the ranges can be satisfied. Overlapping and adjacent
+// ranges are coalesced, end offsets are exclusive.
+fn parse_ranges(value: &str, total: u64) -> Option<Vec<(u64, u64)>> {
+    let specs = value.trim().strip_prefix("bytes=")?;
+    let mut ranges = vec![];
+    for spec in specs.split(',').map(str::trim) {
+        let (first, last) = spec.split_once('-')?;
+        let range = match (first.trim(), last.trim()) {
+            ("", suffix) => {
+                let suffix: u64 = suffix.parse().ok()?;
+                (suffix > 0).then(|| (total.saturating_sub(suffix), total))
+            }
+            (first, "") => {
+                let first: u64 = first.parse().ok()?;
+                Some((first, total))
+            }
+            (first, last) => {
+                let first: u64 = first.parse().ok()?;
+                let last: u64 = last.parse().ok()?;
+                if last < first {
+                    return None;
+                }
+                Some((first, last.saturating_add(1).min(total)))
+            }
+        };
+        if let Some((start, end)) = range
+            && start < end
+        {
+            ranges.push((start, end));
+        }
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = vec![];
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    Some(merged)
+}
@@ -0,0 +1,169 @@
+use crate::analysis::start_analysis;
+use crate::index;
+use crate::library::Library;
+use crate::{AppState, HashingCache, TraverseError, audiofiles, traverse_dir};
+use actix_web::web;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+const MAX_REPORTED_ERRORS: usize = 100;
+
+#[derive(Default)]
+pub struct ScanProgress {
+    pub scanning: AtomicBool,
+    // A full scan asked for while `scanning` was held, started once it is released.
+    pub rescan_requested: AtomicBool,
+    pub directories: AtomicU64,
+    pub files: AtomicU64,
+    pub hashed: AtomicU64,
+    pub reused: AtomicU64,
+    pub errors: AtomicU64,
+    pub error_messages: Mutex<Vec<String>>,
+    pub started: Mutex<Option<std::time::SystemTime>>,
+    pub finished: Mutex<Option<std::time::SystemTime>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct ScanStatus {
+    pub scanning: bool,
+    pub directories: u64,
+    pub files: u64,
+    pub hashed: u64,
+    pub reused: u64,
+    pub errors: u64,
+    pub error_messages: Vec<String>,
+    pub started: Option<u64>,
+    pub finished: Option<u64>,
+}
+
+impl ScanProgress {
+    pub fn error(&self, message: String) {
+        eprintln!("{message}");
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut messages) = self.error_messages.lock()
+            && messages.len() < MAX_REPORTED_ERRORS
+        {
+            messages.push(message);
+        }
+    }
+
+    fn reset(&self) {
+        for counter in [
+            &self.directories,
+            &self.files,
+            &self.hashed,
+            &self.reused,
+            &self.errors,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        if let Ok(mut messages) = self.error_messages.lock() {
+            messages.clear();
+        }
+        if let Ok(mut started) = self.started.lock() {
+            *started = Some(std::time::SystemTime::now());
+        }
+        if let Ok(mut finished) = self.finished.lock() {
+            *finished = None;
+        }
+    }
+
+    pub fn status(&self) -> ScanStatus {
+        let timestamp = |time: &Mutex<Option<std::time::SystemTime>>| {
+            time.lock().ok().and_then(|time| {
+                time.and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs())
+            })
+        };
+        ScanStatus {
+            scanning: self.scanning.load(Ordering::Relaxed),
+            directories: self.directories.load(Ordering::Relaxed),
+            files: self.files.load(Ordering::Relaxed),
+            hashed: self.hashed.load(Ordering::Relaxed),
+            reused: self.reused.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            error_messages: self
+                .error_messages
+                .lock()
+                .map(|messages| messages.clone())
+                .unwrap_or_default(),
+            started: timestamp(&self.started),
+            finished: timestamp(&self.finished),
+        }
+    }
+}
+
+// Only one scan runs at a time, a request made while scanning
+// reports the progress of the scan already in flight.
+pub fn start_scan(data: web::Data<AppState>) -> bool {
+    if data
+        .scan_progress
+        .scanning
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        return false;
+    }
+    data.scan_progress
+        .rescan_requested
+        .store(false, Ordering::SeqCst);
+    data.scan_progress.reset();
+
+    std::thread::spawn(move || {
+        match rescan(&data) {
+            Ok(()) => start_analysis(data.clone()),
+            Err(err) => data.scan_progress.error(format!("{err:?}")),
+        }
+        if let Ok(mut finished) = data.scan_progress.finished.lock() {
+            *finished = Some(std::time::SystemTime::now());
+        }
+        finish_scanning(&data);
+    });
+
+    true
+}
+
+// Unlike `start_scan`, a scan that is needed because changes went unseen is
+// not dropped while a scan or the watcher holds `scanning`, it runs afterwards.
+pub fn request_rescan(data: web::Data<AppState>) {
+    data.scan_progress
+        .rescan_requested
+        .store(true, Ordering::SeqCst);
+    start_scan(data);
+}
+
+// Releases `scanning` and starts the full scan requested while it was held.
+pub fn finish_scanning(data: &web::Data<AppState>) {
+    data.scan_progress.scanning.store(false, Ordering::SeqCst);
+    if data.scan_progress.rescan_requested.load(Ordering::SeqCst) {
+        start_scan(data.clone());
+    }
+}
+
+fn rescan(data: &AppState) -> Result<(), TraverseError> {
+    let cache = data.hashing_cache.lock().map_err(poisoned)?.clone();
+    let ids = data.settings.library.ids;
+    let (_, cache) = traverse_dir(&data.base_dir, cache, ids, &data.scan_progress)?;
+    update_library(data, cache, &data.scan_progress)
+}
+
+// Replaces the library with one built from `cache` and stores the index.
+pub fn update_library(
+    data: &AppState,
+    cache: HashingCache,
+    progress: &ScanProgress,
+) -> Result<(), TraverseError> {
+    let library = Library::new(&data.base_dir, audiofiles(&cache), &cache, &data.cache_dir);
+
+    data.set_library(library).map_err(poisoned)?;
+    if let Err(err) = index::save(&data.state_dir, data.settings.library.ids, &cache) {
+        progress.error(format!("Failed to save the library index: {err:?}"));
+    }
+    *data.hashing_cache.lock().map_err(poisoned)? = cache;
+
+    Ok(())
+}
+
+pub fn poisoned<T>(_: T) -> TraverseError {
+    TraverseError::ThreadError("Poisoned library state".to_string())
+}
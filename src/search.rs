@@ -0,0 +1,184 @@
+use crate::hex_encode;
+use crate::library::Library;
+use md5::{Digest, Md5};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+
+const INDEX_FILE: &str = "search_index.json";
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct SearchIndex {
+    pub fingerprint: String,
+    pub tokens: BTreeMap<String, BTreeSet<String>>,
+}
+
+pub struct SearchMatches {
+    pub artists: Vec<String>,
+    pub albums: Vec<String>,
+    pub songs: Vec<String>,
+}
+
+impl SearchIndex {
+    pub fn new(library: &Library) -> SearchIndex {
+        let mut index = SearchIndex {
+            fingerprint: fingerprint(library),
+            tokens: BTreeMap::new(),
+        };
+
+        for artist in library.artists.values() {
+            index.insert(&artist.id, &artist.name);
+        }
+        for album in library.albums.values() {
+            index.insert(&album.id, &album.name);
+            index.insert(&album.id, &album.artist);
+            if let Some(genre) = &album.genre {
+                index.insert(&album.id, genre);
+            }
+        }
+        for (hash, tags) in library.tracks.iter() {
+            for field in [
+                &tags.title,
+                &tags.artist,
+                &tags.album,
+                &tags.album_artist,
+                &tags.genre,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                index.insert(hash, field);
+            }
+            if let Some(path) = library.audiofiles.get(hash) {
+                index.insert(hash, &library.relative_path(path));
+            }
+        }
+
+        index
+    }
+
+    fn insert(&mut self, id: &str, text: &str) {
+        for token in tokenize(text) {
+            self.tokens.entry(token).or_default().insert(id.to_owned());
+        }
+    }
+
+    // Every query token has to match the beginning of an indexed token.
+    // An empty query matches everything, which clients use to sync the library.
+    pub fn search(&self, library: &Library, query: &str) -> SearchMatches {
+        let query_tokens = tokenize(query.trim_matches('"'));
+        let ids: BTreeSet<&String> = if query_tokens.is_empty() {
+            self.tokens.values().flatten().collect()
+        } else {
+            let mut ids: Option<BTreeSet<&String>> = None;
+            for token in query_tokens {
+                let matching: BTreeSet<&String> = self
+                    .tokens
+                    .range(token.clone()..)
+                    .take_while(|(key, _)| key.starts_with(&token))
+                    .flat_map(|(_, ids)| ids)
+                    .collect();
+                ids = Some(match ids {
+                    Some(ids) => ids.intersection(&matching).copied().collect(),
+                    None => matching,
+                });
+            }
+            ids.unwrap_or_default()
+        };
+
+        let mut artists: Vec<String> = ids
+            .iter()
+            .filter(|id| library.artists.contains_key(id.as_str()))
+            .map(|id| id.to_string())
+            .collect();
+        artists.sort_by_key(|id| library.artists.get(id).map(|a| a.name.to_lowercase()));
+        let mut albums: Vec<String> = ids
+            .iter()
+            .filter(|id| library.albums.contains_key(id.as_str()))
+            .map(|id| id.to_string())
+            .collect();
+        albums.sort_by_key(|id| library.albums.get(id).map(|a| a.name.to_lowercase()));
+        let mut songs: Vec<String> = ids
+            .iter()
+            .filter(|id| library.tracks.contains_key(id.as_str()))
+            .map(|id| id.to_string())
+            .collect();
+        songs.sort_by_key(|id| {
+            library
+                .tracks
+                .get(id)
+                .map(|t| (t.title.as_ref().map(|t| t.to_lowercase()), id.to_owned()))
+        });
+
+        SearchMatches {
+            artists,
+            albums,
+            songs,
+        }
+    }
+
+    pub fn load(cache_dir: &std::path::Path) -> Option<SearchIndex> {
+        let data = std::fs::read(cache_dir.join(INDEX_FILE)).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    pub fn save(&self, cache_dir: &std::path::Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(cache_dir)?;
+        let tmp_path = cache_dir.join(format!(".{INDEX_FILE}.tmp"));
+        let mut tmp = std::fs::File::create(&tmp_path)?;
+        tmp.write_all(&serde_json::to_vec(self)?)?;
+        tmp.sync_all()?;
+        std::fs::rename(tmp_path, cache_dir.join(INDEX_FILE))
+    }
+}
+
+// The index only depends on the indexed tracks and their tags. Audio IDs
+// stay the same when a file is retagged, so the tags are hashed as well.
+pub fn fingerprint(library: &Library) -> String {
+    let mut files: Vec<(&String, String)> = library
+        .audiofiles
+        .iter()
+        .map(|(hash, path)| (hash, library.relative_path(path)))
+        .collect();
+    files.sort_unstable();
+    let mut hasher = Md5::new();
+    for (hash, path) in files {
+        hasher.update(hash.as_bytes());
+        hasher.update(path.as_bytes());
+        let Some(tags) = library.tracks.get(hash) else {
+            continue;
+        };
+        for field in [
+            &tags.title,
+            &tags.artist,
+            &tags.album,
+            &tags.album_artist,
+            &tags.genre,
+        ] {
+            hasher.update([0]);
+            hasher.update(field.as_deref().unwrap_or_default().as_bytes());
+        }
+    }
+    hex_encode(hasher.finalize().to_vec())
+}
+
+pub fn load_or_build(library: &Library, cache_dir: &std::path::Path) -> SearchIndex {
+    if let Some(index) = SearchIndex::load(cache_dir)
+        && index.fingerprint == fingerprint(library)
+    {
+        return index;
+    }
+
+    let index = SearchIndex::new(library);
+    if let Err(err) = index.save(cache_dir) {
+        eprintln!("Failed to save the search index: {err:?}");
+    }
+    index
+}
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_string())
+        .collect()
+}
@@ -0,0 +1,244 @@
+use std::io::{Read, Seek as IoSeek, SeekFrom};
+
+pub struct Seek {
+    pub prefix: Vec<u8>,
+    pub offset: u64,
+}
+
+// Formats without a container index can be started mid-file by seeking:
+// WAV by byte rate and MP3 by bitrate, resynchronized on a frame header.
+pub fn seek(path: &std::path::Path, time_offset: f64) -> Option<Seek> {
+    if time_offset <= 0.0 {
+        return None;
+    }
+    let mut file = std::fs::File::open(path).ok()?;
+    match path.extension()?.to_str()?.to_lowercase().as_str() {
+        "wav" => seek_wav(&mut file, time_offset),
+        "mp3" => seek_mp3(&mut file, path, time_offset),
+        _ => None,
+    }
+}
+
+// The extensible `fmt ` chunk is 40 bytes, anything much larger is not a WAV
+// worth seeking in and is never read into memory.
+const MAX_FMT_SIZE: u64 = 256;
+
+fn seek_wav(file: &mut std::fs::File, time_offset: f64) -> Option<Seek> {
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header).ok()?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let mut fmt = None;
+    loop {
+        let mut chunk_header = [0u8; 8];
+        file.read_exact(&mut chunk_header).ok()?;
+        let size = u32::from_le_bytes(chunk_header[4..8].try_into().ok()?) as u64;
+        match &chunk_header[0..4] {
+            b"fmt " => {
+                if size > MAX_FMT_SIZE {
+                    return None;
+                }
+                let mut chunk = vec![0u8; size as usize];
+                file.read_exact(&mut chunk).ok()?;
+                if size % 2 == 1 {
+                    file.seek(SeekFrom::Current(1)).ok()?;
+                }
+                fmt = Some(chunk);
+            }
+            b"data" => {
+                let fmt = fmt?;
+                let byte_rate = u32::from_le_bytes(fmt.get(8..12)?.try_into().ok()?) as u64;
+                let block_align = u16::from_le_bytes(fmt.get(12..14)?.try_into().ok()?) as u64;
+                if block_align == 0 {
+                    return None;
+                }
+                let data_start = file.stream_position().ok()?;
+                let skip = (time_offset * byte_rate as f64) as u64 / block_align * block_align;
+                if skip >= size {
+                    return None;
+                }
+                let remaining = (size - skip) as u32;
+
+                let mut prefix = vec![];
+                prefix.extend_from_slice(b"RIFF");
+                prefix.extend_from_slice(&(4 + 8 + fmt.len() as u32 + 8 + remaining).to_le_bytes());
+                prefix.extend_from_slice(b"WAVE");
+                prefix.extend_from_slice(b"fmt ");
+                prefix.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+                prefix.extend_from_slice(&fmt);
+                prefix.extend_from_slice(b"data");
+                prefix.extend_from_slice(&remaining.to_le_bytes());
+                return Some(Seek {
+                    prefix,
+                    offset: data_start + skip,
+                });
+            }
+            _ => {
+                file.seek(SeekFrom::Current((size + size % 2) as i64))
+                    .ok()?;
+            }
+        }
+    }
+}
+
+fn seek_mp3(file: &mut std::fs::File, path: &std::path::Path, time_offset: f64) -> Option<Seek> {
+    use lofty::file::AudioFile;
+
+    let duration = lofty::read_from_path(path)
+        .ok()?
+        .properties()
+        .duration()
+        .as_secs_f64();
+    if time_offset >= duration {
+        return None;
+    }
+
+    let file_len = file.metadata().ok()?.len();
+    let audio_start = id3v2_size(file)?;
+    let audio_len = file_len.checked_sub(audio_start)?;
+    let target = audio_start + (audio_len as f64 * time_offset / duration) as u64;
+
+    file.seek(SeekFrom::Start(target)).ok()?;
+    let mut window = vec![0u8; 64 * 1024];
+    let read = file.read(&mut window).ok()?;
+    let window = &window[..read];
+    for i in 0..window.len().saturating_sub(4) {
+        if let Some(frame_len) = mp3_frame_len(&window[i..]) {
+            // A second header right after the first rules out false syncs in audio data.
+            if window
+                .get(i + frame_len..)
+                .is_some_and(|next| mp3_frame_len(next).is_some())
+            {
+                return Some(Seek {
+                    prefix: vec![],
+                    offset: target + i as u64,
+                });
+            }
+        }
+    }
+
+    None
+}
+
+pub fn id3v2_size(file: &mut std::fs::File) -> Option<u64> {
+    let mut header = [0u8; 10];
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_exact(&mut header).ok()?;
+    if &header[0..3] != b"ID3" {
+        return Some(0);
+    }
+    let size = header[6..10]
+        .iter()
+        .fold(0u64, |acc, b| (acc << 7) | (*b & 0x7f) as u64);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Some(10 + size + footer)
+}
+
+pub fn mp3_frame_len(header: &[u8]) -> Option<usize> {
+    mp3_frame(header).map(|frame| frame.len)
+}
+
+pub struct Mp3Frame {
+    pub len: usize,
+    pub samples: usize,
+    pub sample_rate: usize,
+}
+
+pub fn mp3_frame(header: &[u8]) -> Option<Mp3Frame> {
+    if header.len() < 4 || header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+    let version = (header[1] >> 3) & 0x03;
+    let layer = (header[1] >> 1) & 0x03;
+    let bitrate_index = (header[2] >> 4) as usize;
+    let sample_rate_index = ((header[2] >> 2) & 0x03) as usize;
+    let padding = ((header[2] >> 1) & 0x01) as usize;
+    if version == 1 || layer != 1 || bitrate_index == 0 || bitrate_index == 15 {
+        return None;
+    }
+    if sample_rate_index == 3 {
+        return None;
+    }
+
+    const BITRATES_V1: [usize; 15] = [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ];
+    const BITRATES_V2: [usize; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+    const SAMPLE_RATES: [usize; 3] = [44100, 48000, 32000];
+
+    let (bitrate, sample_rate, samples) = match version {
+        3 => (
+            BITRATES_V1[bitrate_index],
+            SAMPLE_RATES[sample_rate_index],
+            1152,
+        ),
+        2 => (
+            BITRATES_V2[bitrate_index],
+            SAMPLE_RATES[sample_rate_index] / 2,
+            576,
+        ),
+        _ => (
+            BITRATES_V2[bitrate_index],
+            SAMPLE_RATES[sample_rate_index] / 4,
+            576,
+        ),
+    };
+
+    Some(Mp3Frame {
+        len: samples / 8 * bitrate * 1000 / sample_rate + padding,
+        samples,
+        sample_rate,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    // One second of 8 kHz 8-bit mono silence, with the `fmt ` size as given.
+    fn wav(fmt_size: u32) -> Vec<u8> {
+        let mut wav = vec![];
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(4 + 8 + 16 + 8 + 8000u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(b"LIST");
+        wav.extend_from_slice(&3u32.to_le_bytes());
+        wav.extend_from_slice(&[0; 4]);
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&fmt_size.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&8u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&[0x80; 8000]);
+        wav
+    }
+
+    #[test]
+    fn seeks_wav_past_other_chunks() {
+        let dir = TestDir::new("seek-wav");
+        let path = dir.join("track.wav");
+        std::fs::write(&path, wav(16)).unwrap();
+
+        let seek = seek(&path, 0.5).unwrap();
+        assert_eq!(seek.offset, 12 + 12 + 24 + 8 + 4000);
+        assert_eq!(&seek.prefix[36..40], b"data");
+        assert_eq!(&seek.prefix[40..44], &4000u32.to_le_bytes());
+    }
+
+    #[test]
+    fn oversized_fmt_chunks_are_not_read() {
+        let dir = TestDir::new("seek-wav-fmt");
+        let path = dir.join("track.wav");
+        std::fs::write(&path, wav(u32::MAX)).unwrap();
+
+        assert!(seek(&path, 0.5).is_none());
+    }
+}
@@ -1,20 +1,33 @@
+use crate::artwork::embedded_artwork;
+use crate::bookmarks::BookmarkError;
+use crate::chapters::chapter_image;
+use crate::conditional::{REVALIDATE, Validators};
+use crate::hls;
+use crate::lyrics::{read_lyrics, sidecar_version};
+use crate::random::{RandomFilter, random_songs};
+use crate::range::{Source, respond};
+use crate::scan::start_scan;
+use crate::transcode::{Format, TranscodeError, negotiate, requested};
+use crate::transcode_cache::transcoded_response;
 use crate::{
-    AppState, AudioFile, AudioFileMetadata, PingResponse, TraverseError, extension_to_mime,
-    traverse_dir,
+    AppState, AudioFile, AudioFileChapter, AudioFileMetadata, DuplicateGroup, PingResponse,
+    SearchAlbum, SearchArtist, SearchResponse, TraverseError, extension_to_mime,
 };
-use actix_web::{CustomizeResponder, HttpRequest, HttpResponse, Responder, get, web};
+use actix_web::{HttpRequest, HttpResponse, Responder, delete, get, post, web};
 use lofty::{
     file::{AudioFile as LofyAudioFile, TaggedFileExt},
     tag::Accessor,
 };
-use rand::Rng;
 
 #[derive(Debug)]
 pub enum ServiceError {
     PoisonError,
     ValuesExtractionError,
+    NotFound(String),
+    BadRequest(String),
     TraverseError(TraverseError),
     SerdeJsonError(serde_json::Error),
+    BookmarkError(BookmarkError),
 }
 
 impl From<TraverseError> for ServiceError {
@@ -29,95 +42,149 @@ impl From<serde_json::Error> for ServiceError {
     }
 }
 
+impl From<BookmarkError> for ServiceError {
+    fn from(err: BookmarkError) -> Self {
+        ServiceError::BookmarkError(err)
+    }
+}
+
+fn error_response(err: ServiceError) -> HttpResponse {
+    match err {
+        ServiceError::NotFound(message) => HttpResponse::NotFound().body(message),
+        ServiceError::BadRequest(message) => HttpResponse::BadRequest().body(message),
+        _ => HttpResponse::InternalServerError().body("Internal Server Error"),
+    }
+}
+
 #[get("/")]
-async fn home(data: web::Data<AppState>) -> impl Responder {
-    if let Ok(responder) = _home(data) {
-        responder
-    } else {
-        HttpResponse::InternalServerError()
-            .body("Internal Server Error")
-            .customize()
+async fn home(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    filter: web::Query<RandomFilter>,
+) -> impl Responder {
+    match _home(&req, data, filter.into_inner()) {
+        Ok(response) => response,
+        Err(ServiceError::NotFound(message)) => HttpResponse::NotFound().body(message),
+        Err(_) => HttpResponse::InternalServerError().body("Internal Server Error"),
     }
 }
 
-fn _home(data: web::Data<AppState>) -> Result<CustomizeResponder<HttpResponse>, ServiceError> {
-    let audiofiles = data
-        .audiofiles
-        .lock()
-        .map_err(|_| ServiceError::PoisonError)?;
-    let audiofiles_len = audiofiles.values().count();
-    let audiofiles = audiofiles.values();
-    let mut rng = rand::rng();
-    let i = rng.random_range(..audiofiles_len);
+fn _home(
+    req: &HttpRequest,
+    data: web::Data<AppState>,
+    filter: RandomFilter,
+) -> Result<HttpResponse, ServiceError> {
+    let library = data.library()?;
+    let filter = RandomFilter {
+        size: Some(1),
+        ..filter
+    };
+    let hash = random_songs(&library, &filter)
+        .pop()
+        .ok_or(ServiceError::NotFound(
+            "No matching audio files".to_string(),
+        ))?;
 
     let values = (|| {
-        let file = audiofiles.skip(i - 1).next()?;
+        let file = library.audiofiles.get(&hash)?;
         let file_ext = file.extension()?;
         let file_name = file.file_name()?;
         let mime = extension_to_mime(file_ext)?;
-        let file_body = std::fs::read(file).ok()?;
-        Some((file_body, file_name, mime))
+        Some((file, file_name, mime))
     })();
 
-    let (file_body, file_name, mime) = values.ok_or(ServiceError::ValuesExtractionError)?;
+    let (file, file_name, mime) = values.ok_or(ServiceError::ValuesExtractionError)?;
 
-    Ok(HttpResponse::Ok()
-        .content_type(mime)
-        .body(file_body)
-        .customize()
-        .insert_header((
-            "Content-Disposition",
-            format!("inline; filename*=UTF-8''{}", file_name.to_string_lossy()),
-        )))
+    let mut response = HttpResponse::Ok();
+    response.insert_header((
+        "Content-Disposition",
+        format!("inline; filename*=UTF-8''{}", file_name.to_string_lossy()),
+    ));
+    let validators = Validators::file(&data, &hash, file);
+    respond(req, response, Source::new(file), &mime, &validators)
+        .map_err(|_| ServiceError::ValuesExtractionError)
 }
 
-#[get("/scan")]
-async fn scan(data: web::Data<AppState>) -> impl Responder {
-    if let Ok(responder) = _scan(data) {
+#[get("/random")]
+async fn get_random_files(
+    data: web::Data<AppState>,
+    filter: web::Query<RandomFilter>,
+) -> impl Responder {
+    if let Ok(responder) = _get_random_files(data, filter.into_inner()) {
         responder
     } else {
         HttpResponse::InternalServerError().body("Internal Server Error")
     }
 }
 
-fn _scan(data: web::Data<AppState>) -> Result<HttpResponse, ServiceError> {
-    let mut cache = data
-        .hashing_cache
-        .lock()
-        .map_err(|_| ServiceError::PoisonError)?;
+fn _get_random_files(
+    data: web::Data<AppState>,
+    filter: RandomFilter,
+) -> Result<HttpResponse, ServiceError> {
+    let library = data.library()?;
+    let audiofiles: Vec<AudioFile> = random_songs(&library, &filter)
+        .iter()
+        .filter_map(|hash| {
+            let f = library.audiofiles.get(hash)?;
+            let mime = extension_to_mime(f.extension()?)?;
+            Some(AudioFile {
+                id: hash.to_owned(),
+                path: format!("{f:?}"),
+                mime,
+            })
+        })
+        .collect();
 
-    let (files, updated_cache) = traverse_dir(&data.base_dir, cache.clone())?;
-    let mut audiofiles = data
-        .audiofiles
-        .lock()
-        .map_err(|_| ServiceError::PoisonError)?;
-    *audiofiles = files.clone();
-    *cache = updated_cache;
+    let audiofiles_json = serde_json::to_vec(&audiofiles)?;
 
-    let files = files.iter().map(|(k, v)| format!("{}:{:?}\n", k, v));
-    let mut files = files.collect::<Vec<String>>();
-    files.sort_unstable();
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .body(audiofiles_json))
+}
+
+#[get("/scan")]
+async fn scan(data: web::Data<AppState>) -> impl Responder {
+    start_scan(data.clone());
+    scan_status_response(&data, actix_web::http::StatusCode::ACCEPTED)
+}
 
-    return Ok(HttpResponse::Ok()
-        .content_type("text/plain; charset=utf-8")
-        .body(files.concat()));
+#[get("/scan/status")]
+async fn get_scan_status(data: web::Data<AppState>) -> impl Responder {
+    scan_status_response(&data, actix_web::http::StatusCode::OK)
+}
+
+fn scan_status_response(data: &AppState, status: actix_web::http::StatusCode) -> HttpResponse {
+    if let Ok(body) = serde_json::to_vec(&data.scan_progress.status()) {
+        HttpResponse::build(status)
+            .content_type("application/json; charset=utf-8")
+            .body(body)
+    } else {
+        HttpResponse::InternalServerError().body("Internal Server Error")
+    }
 }
 
 #[get("/files")]
-async fn get_files(data: web::Data<AppState>) -> impl Responder {
-    if let Ok(responder) = _get_files(data) {
+async fn get_files(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    if let Ok(responder) = _get_files(&req, data) {
         responder
     } else {
         HttpResponse::InternalServerError().body("Internal Server Error")
     }
 }
 
-fn _get_files(data: web::Data<AppState>) -> Result<HttpResponse, ServiceError> {
-    let audiofiles = data
+fn _get_files(req: &HttpRequest, data: web::Data<AppState>) -> Result<HttpResponse, ServiceError> {
+    let library = data.library()?;
+    let validators = Validators::new(
+        &library.search.fingerprint,
+        Some(library.last_modified),
+        REVALIDATE,
+    );
+    let mut response = match validators.response(req) {
+        Ok(response) => response,
+        Err(not_modified) => return Ok(not_modified),
+    };
+    let audiofiles: Vec<AudioFile> = library
         .audiofiles
-        .lock()
-        .map_err(|_| ServiceError::PoisonError)?;
-    let audiofiles: Vec<AudioFile> = audiofiles
         .iter()
         .filter_map(|(hash, f)| {
             let mime = extension_to_mime(f.extension()?)?;
@@ -131,45 +198,260 @@ fn _get_files(data: web::Data<AppState>) -> Result<HttpResponse, ServiceError> {
 
     let audiofiles_json = serde_json::to_vec(&audiofiles)?;
 
-    Ok(HttpResponse::Ok()
+    Ok(response
         .content_type("application/json; charset=utf-8")
         .body(audiofiles_json))
 }
 
+#[derive(serde::Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+}
+
+#[get("/search")]
+async fn search(data: web::Data<AppState>, query: web::Query<SearchQuery>) -> impl Responder {
+    if let Ok(responder) = _search(data, query) {
+        responder
+    } else {
+        HttpResponse::InternalServerError().body("Internal Server Error")
+    }
+}
+
+fn _search(
+    data: web::Data<AppState>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let library = data.library()?;
+    let matches = library.search.search(&library, &query.q);
+
+    let response = SearchResponse {
+        artists: matches
+            .artists
+            .iter()
+            .filter_map(|id| library.artists.get(id))
+            .map(|artist| SearchArtist {
+                id: artist.id.clone(),
+                name: artist.name.clone(),
+            })
+            .collect(),
+        albums: matches
+            .albums
+            .iter()
+            .filter_map(|id| library.albums.get(id))
+            .map(|album| SearchAlbum {
+                id: album.id.clone(),
+                name: album.name.clone(),
+                artist: album.artist.clone(),
+            })
+            .collect(),
+        files: matches
+            .songs
+            .iter()
+            .filter_map(|hash| {
+                let f = library.audiofiles.get(hash)?;
+                let mime = extension_to_mime(f.extension()?)?;
+                Some(AudioFile {
+                    id: hash.to_owned(),
+                    path: format!("{f:?}"),
+                    mime,
+                })
+            })
+            .collect(),
+    };
+
+    let response_json = serde_json::to_vec(&response)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .body(response_json))
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscodeQuery {
+    pub format: Option<String>,
+    pub max_bit_rate: Option<u32>,
+    pub c: Option<String>,
+    pub u: Option<String>,
+}
+
 #[get("/file/{id}")]
-async fn get_file_by_id(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+async fn get_file_by_id(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<TranscodeQuery>,
+) -> impl Responder {
     let hash = path.into_inner();
-    if let Ok(audiofiles) = data.audiofiles.lock() {
-        if let Some(file) = audiofiles.get(&hash) {
+    if let Ok(library) = data.library() {
+        if let Some(file) = library.audiofiles.get(&hash) {
+            let bitrate = library.tracks.get(&hash).and_then(|tags| tags.bitrate);
+            let user_agent = req
+                .headers()
+                .get("User-Agent")
+                .and_then(|value| value.to_str().ok());
+            let (format, max_bit_rate) = requested(
+                &data.settings.transcoding,
+                query.format.as_deref(),
+                query.max_bit_rate,
+                query.c.as_deref(),
+                user_agent,
+                query.u.as_deref(),
+            );
+            let profile = match negotiate(
+                &data.settings.transcoding,
+                file,
+                bitrate,
+                format,
+                max_bit_rate,
+            ) {
+                Ok(profile) => profile,
+                Err(err) => return transcode_error(err),
+            };
+            if let Some(profile) = profile {
+                return transcoded_response(&data, &req, &hash, file, profile, None)
+                    .await
+                    .unwrap_or_else(transcode_error);
+            }
+
             let values = (|| {
                 let file_ext = file.extension()?;
                 let file_name = file.file_name()?;
                 let mime = extension_to_mime(file_ext)?;
-
-                let file_body = std::fs::read(file).ok()?;
-                Some((file_body, file_name, mime))
+                Some((file_name, mime))
             })();
-            if let Some((file_body, file_name, mime)) = values {
-                return HttpResponse::Ok()
-                    .content_type(mime)
-                    .body(file_body)
-                    .customize()
-                    .insert_header((
-                        "Content-Disposition",
-                        format!("inline; filename*=UTF-8''{}", file_name.to_string_lossy()),
-                    ));
-            } else {
-                return HttpResponse::InternalServerError()
-                    .body("Internal Server Error")
-                    .customize();
+            if let Some((file_name, mime)) = values {
+                let validators = Validators::file(&data, &hash, file);
+                let mut response = match validators.response(&req) {
+                    Ok(response) => response,
+                    Err(not_modified) => return not_modified,
+                };
+                response.insert_header((
+                    "Content-Disposition",
+                    format!("inline; filename*=UTF-8''{}", file_name.to_string_lossy()),
+                ));
+                if let Ok(response) = respond(&req, response, Source::new(file), &mime, &validators)
+                {
+                    return response;
+                }
             }
+            return HttpResponse::InternalServerError().body("Internal Server Error");
         } else {
-            return HttpResponse::NotFound().body("Invalid hash").customize();
+            return HttpResponse::NotFound().body("Invalid hash");
         }
     }
-    HttpResponse::InternalServerError()
-        .body("Internal Server Error")
-        .customize()
+    HttpResponse::InternalServerError().body("Internal Server Error")
+}
+
+fn transcode_error(err: TranscodeError) -> HttpResponse {
+    match err {
+        TranscodeError::UnsupportedFormat(_) | TranscodeError::NoEncoder(_) => {
+            HttpResponse::BadRequest().body(format!("{err:?}"))
+        }
+        TranscodeError::Undecodable => {
+            HttpResponse::UnsupportedMediaType().body(format!("{err:?}"))
+        }
+        _ => HttpResponse::InternalServerError().body("Internal Server Error"),
+    }
+}
+
+#[get("/file/{id}/hls.m3u8")]
+async fn get_file_hls_playlist(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<TranscodeQuery>,
+) -> impl Responder {
+    let hash = path.into_inner();
+    let Ok(library) = data.library() else {
+        return HttpResponse::InternalServerError().body("Internal Server Error");
+    };
+    let Some(file) = library.audiofiles.get(&hash) else {
+        return HttpResponse::NotFound().body("Invalid hash");
+    };
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|value| value.to_str().ok());
+    let (format, max_bit_rate) = requested(
+        &data.settings.transcoding,
+        query.format.as_deref(),
+        query.max_bit_rate,
+        query.c.as_deref(),
+        user_agent,
+        query.u.as_deref(),
+    );
+    let Some(tags) = library.tracks.get(&hash) else {
+        return HttpResponse::NotFound().body("Invalid hash");
+    };
+    let encoding = match hls::encoding(
+        &data.settings.transcoding,
+        file,
+        tags.bitrate,
+        format,
+        max_bit_rate,
+    ) {
+        Ok(encoding) => encoding,
+        Err(err) => return transcode_error(err),
+    };
+    let Some(duration) = hls::duration(tags) else {
+        return HttpResponse::InternalServerError().body("Internal Server Error");
+    };
+    HttpResponse::Ok()
+        .content_type(hls::PLAYLIST_MIME)
+        .insert_header(("Cache-Control", REVALIDATE))
+        .body(hls::playlist(duration, encoding))
+}
+
+#[get("/file/{id}/hls/{bitrate}/{segment}")]
+async fn get_file_hls_segment(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<(String, String, String)>,
+) -> impl Responder {
+    let (hash, variant, segment) = path.into_inner();
+    let Ok(library) = data.library() else {
+        return HttpResponse::InternalServerError().body("Internal Server Error");
+    };
+    let Some(file) = library.audiofiles.get(&hash) else {
+        return HttpResponse::NotFound().body("Invalid hash");
+    };
+    let Some((index, format)) = segment
+        .split_once('.')
+        .and_then(|(index, format)| Some((index.parse::<u64>().ok()?, format)))
+    else {
+        return HttpResponse::NotFound().body("Invalid segment");
+    };
+    if library
+        .tracks
+        .get(&hash)
+        .and_then(hls::duration)
+        .is_none_or(|duration| index >= hls::segment_count(duration))
+    {
+        return HttpResponse::NotFound().body("Invalid segment");
+    }
+    if variant == "raw" {
+        let source = file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Format::from_name);
+        if source != Some(Format::Mp3) || Format::from_name(format) != source {
+            return HttpResponse::NotFound().body("Invalid segment");
+        }
+        return hls::source_segment_response(&data, &req, &hash, file, index)
+            .await
+            .unwrap_or_else(|_| HttpResponse::InternalServerError().body("Internal Server Error"));
+    }
+    let Ok(bitrate) = variant.parse::<u32>() else {
+        return HttpResponse::NotFound().body("Invalid segment");
+    };
+    let profile = match hls::profile(&data.settings.transcoding, Some(format), Some(bitrate)) {
+        Ok(profile) => profile,
+        Err(err) => return transcode_error(err),
+    };
+    hls::segment_response(&data, &req, &hash, file, profile, index)
+        .await
+        .unwrap_or_else(transcode_error)
 }
 
 #[get("/file/{id}/metadata")]
@@ -179,8 +461,22 @@ async fn get_file_metadata_by_id(
     path: web::Path<String>,
 ) -> impl Responder {
     let hash = path.into_inner();
-    if let Ok(audiofiles) = data.audiofiles.lock() {
-        if let Some(file) = audiofiles.get(&hash) {
+    if let Ok(library) = data.library() {
+        if let Some(file) = library.audiofiles.get(&hash) {
+            // Loudness analysis fills in values later and lyrics sidecar files
+            // come and go, so the metadata is revalidated against an ETag
+            // that includes both.
+            let analyzed = data.loudness.get(&library, &hash);
+            let mut validators = Validators::file(&data, &hash, file)
+                .with_suffix("metadata")
+                .with_suffix(&analyzed.etag())
+                .with_suffix(&sidecar_version(file));
+            validators.cache_control = REVALIDATE;
+            validators.last_modified = None;
+            let mut response = match validators.response(&req) {
+                Ok(response) => response,
+                Err(not_modified) => return not_modified,
+            };
             if let Ok(tagged_file) = lofty::read_from_path(file) {
                 let title = tagged_file
                     .tags()
@@ -221,6 +517,17 @@ async fn get_file_metadata_by_id(
                     None
                 };
 
+                let lyrics_url = (!read_lyrics(file).is_empty())
+                    .then(|| req.full_url().join("lyrics").ok())
+                    .flatten()
+                    .map(|url| url.to_string());
+
+                let replay_gain = library
+                    .tracks
+                    .get(&hash)
+                    .map(|tags| tags.replay_gain)
+                    .unwrap_or_default();
+
                 let metadata = AudioFileMetadata {
                     title,
                     artist,
@@ -229,10 +536,18 @@ async fn get_file_metadata_by_id(
                     release_year,
                     duration,
                     artwork_url,
+                    lyrics_url,
+                    track_gain: replay_gain.track_gain.or(analyzed.track_gain),
+                    track_peak: replay_gain.track_peak.or(analyzed.track_peak),
+                    album_gain: replay_gain.album_gain.or(analyzed.album_gain),
+                    album_peak: replay_gain.album_peak.or(analyzed.album_peak),
+                    header_gain: replay_gain.header_gain,
+                    loudness: analyzed.loudness,
+                    album_loudness: analyzed.album_loudness,
                 };
 
                 if let Ok(metadata_json) = serde_json::to_vec(&metadata) {
-                    return HttpResponse::Ok()
+                    return response
                         .content_type("application/json; charset=utf-8")
                         .body(metadata_json);
                 }
@@ -246,20 +561,22 @@ async fn get_file_metadata_by_id(
 
 #[get("/file/{id}/metadata/artwork")]
 async fn get_file_artwork_by_id(
+    req: HttpRequest,
     data: web::Data<AppState>,
     path: web::Path<String>,
 ) -> impl Responder {
     let hash = path.into_inner();
-    if let Ok(audiofiles) = data.audiofiles.lock() {
-        if let Some(file) = &audiofiles.get(&hash) {
-            if let Ok(tagged_file) = lofty::read_from_path(file) {
-                let artwork = tagged_file.tags().iter().flat_map(|t| t.pictures()).next();
-
-                if let Some(artwork) = artwork {
-                    return HttpResponse::Ok().body(artwork.data().to_vec());
-                } else {
-                    return HttpResponse::NotFound().body("No embedded cover art");
-                }
+    if let Ok(library) = data.library() {
+        if let Some(file) = &library.audiofiles.get(&hash) {
+            let validators = Validators::file(&data, &hash, file).with_suffix("artwork");
+            let mut response = match validators.response(&req) {
+                Ok(response) => response,
+                Err(not_modified) => return not_modified,
+            };
+            if let Some(artwork) = embedded_artwork(file) {
+                return response.content_type(artwork.mime).body(artwork.data);
+            } else {
+                return HttpResponse::NotFound().body("No embedded cover art");
             }
         } else {
             return HttpResponse::NotFound().body("Invalid hash");
@@ -268,6 +585,290 @@ async fn get_file_artwork_by_id(
     HttpResponse::InternalServerError().body("Internal Server Error")
 }
 
+#[get("/file/{id}/chapters")]
+async fn get_file_chapters_by_id(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let hash = path.into_inner();
+    let Ok(library) = data.library() else {
+        return HttpResponse::InternalServerError().body("Internal Server Error");
+    };
+    let (Some(file), Some(tags)) = (library.audiofiles.get(&hash), library.tracks.get(&hash))
+    else {
+        return HttpResponse::NotFound().body("Invalid hash");
+    };
+    let validators = Validators::file(&data, &hash, file).with_suffix("chapters");
+    let mut response = match validators.response(&req) {
+        Ok(response) => response,
+        Err(not_modified) => return not_modified,
+    };
+
+    let chapters: Vec<AudioFileChapter> = tags
+        .chapters
+        .iter()
+        .enumerate()
+        .map(|(index, chapter)| AudioFileChapter {
+            title: chapter.title.clone(),
+            start: chapter.start as f64 / 1000.0,
+            end: chapter.end as f64 / 1000.0,
+            image_url: chapter
+                .has_image
+                .then(|| req.full_url().join(&format!("chapters/{index}/image")).ok())
+                .flatten()
+                .map(|url| url.to_string()),
+        })
+        .collect();
+    match serde_json::to_vec(&chapters) {
+        Ok(body) => response
+            .content_type("application/json; charset=utf-8")
+            .body(body),
+        Err(_) => HttpResponse::InternalServerError().body("Internal Server Error"),
+    }
+}
+
+#[get("/file/{id}/chapters/{index}/image")]
+async fn get_file_chapter_image(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    path: web::Path<(String, usize)>,
+) -> impl Responder {
+    let (hash, index) = path.into_inner();
+    let Ok(library) = data.library() else {
+        return HttpResponse::InternalServerError().body("Internal Server Error");
+    };
+    let Some(file) = library.audiofiles.get(&hash) else {
+        return HttpResponse::NotFound().body("Invalid hash");
+    };
+    let validators = Validators::file(&data, &hash, file).with_suffix(&format!("chapter-{index}"));
+    let mut response = match validators.response(&req) {
+        Ok(response) => response,
+        Err(not_modified) => return not_modified,
+    };
+    match chapter_image(file, index) {
+        Some(image) => response.content_type(image.mime).body(image.data),
+        None => HttpResponse::NotFound().body("No chapter image"),
+    }
+}
+
+// Sidecar files can change without the track, so lyrics are always revalidated.
+#[get("/file/{id}/lyrics")]
+async fn get_file_lyrics_by_id(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    _get_file_lyrics_by_id(data, path).unwrap_or_else(error_response)
+}
+
+fn _get_file_lyrics_by_id(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let file = data
+        .library()?
+        .audiofiles
+        .get(&path.into_inner())
+        .cloned()
+        .ok_or(ServiceError::NotFound("Invalid hash".to_string()))?;
+    json_response(&read_lyrics(&file))
+}
+
+// Largest waste of space first.
+#[get("/duplicates")]
+async fn get_duplicates(data: web::Data<AppState>) -> impl Responder {
+    _get_duplicates(data).unwrap_or_else(error_response)
+}
+
+fn _get_duplicates(data: web::Data<AppState>) -> Result<HttpResponse, ServiceError> {
+    let library = data.library()?;
+    let mut groups: Vec<DuplicateGroup> = library
+        .duplicates
+        .iter()
+        .map(|(hash, paths)| DuplicateGroup {
+            id: hash.clone(),
+            size: library.tracks.get(hash).map(|tags| tags.size).unwrap_or(0),
+            paths: paths
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        let wasted = |group: &DuplicateGroup| group.size * (group.paths.len() as u64 - 1);
+        wasted(b)
+            .cmp(&wasted(a))
+            .then_with(|| a.paths.cmp(&b.paths))
+    });
+    json_response(&groups)
+}
+
+// Old file IDs mapped to the current ones since the ID mode last changed.
+#[get("/aliases")]
+async fn get_aliases(data: web::Data<AppState>) -> impl Responder {
+    json_response(&data.aliases.all()).unwrap_or_else(error_response)
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListenerQuery {
+    pub listener: Option<String>,
+}
+
+impl ListenerQuery {
+    fn listener(&self) -> Result<&str, ServiceError> {
+        self.listener
+            .as_deref()
+            .map(str::trim)
+            .filter(|listener| !listener.is_empty())
+            .ok_or(ServiceError::BadRequest("Missing listener".to_string()))
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct BookmarkRequest {
+    pub position: u64,
+    pub comment: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PlayQueueRequest {
+    pub entries: Vec<String>,
+    pub current: Option<String>,
+    #[serde(default)]
+    pub position: u64,
+    pub client: Option<String>,
+}
+
+fn json_response<T: serde::Serialize>(value: &T) -> Result<HttpResponse, ServiceError> {
+    Ok(HttpResponse::Ok()
+        .content_type("application/json; charset=utf-8")
+        .insert_header(("Cache-Control", REVALIDATE))
+        .body(serde_json::to_vec(value)?))
+}
+
+#[get("/bookmarks")]
+async fn get_bookmarks(
+    data: web::Data<AppState>,
+    query: web::Query<ListenerQuery>,
+) -> impl Responder {
+    _get_bookmarks(data, query).unwrap_or_else(error_response)
+}
+
+fn _get_bookmarks(
+    data: web::Data<AppState>,
+    query: web::Query<ListenerQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    json_response(&data.bookmarks.bookmarks(query.listener()?)?)
+}
+
+#[post("/bookmarks/{id}")]
+async fn save_bookmark(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ListenerQuery>,
+    body: web::Json<BookmarkRequest>,
+) -> impl Responder {
+    _save_bookmark(data, path.into_inner(), query, body.into_inner()).unwrap_or_else(error_response)
+}
+
+fn _save_bookmark(
+    data: web::Data<AppState>,
+    hash: String,
+    query: web::Query<ListenerQuery>,
+    body: BookmarkRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let listener = query.listener()?;
+    if !data.library()?.audiofiles.contains_key(&hash) {
+        return Err(ServiceError::NotFound("Invalid hash".to_string()));
+    }
+    let bookmark = data
+        .bookmarks
+        .save_bookmark(listener, &hash, body.position, body.comment)?;
+    json_response(&bookmark)
+}
+
+#[delete("/bookmarks/{id}")]
+async fn delete_bookmark(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ListenerQuery>,
+) -> impl Responder {
+    _delete_bookmark(data, path.into_inner(), query).unwrap_or_else(error_response)
+}
+
+fn _delete_bookmark(
+    data: web::Data<AppState>,
+    hash: String,
+    query: web::Query<ListenerQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    if !data.bookmarks.delete_bookmark(query.listener()?, &hash)? {
+        return Err(ServiceError::NotFound("No bookmark".to_string()));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/queue")]
+async fn get_play_queue(
+    data: web::Data<AppState>,
+    query: web::Query<ListenerQuery>,
+) -> impl Responder {
+    _get_play_queue(data, query).unwrap_or_else(error_response)
+}
+
+fn _get_play_queue(
+    data: web::Data<AppState>,
+    query: web::Query<ListenerQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let play_queue = data
+        .bookmarks
+        .play_queue(query.listener()?)?
+        .ok_or(ServiceError::NotFound("No play queue".to_string()))?;
+    json_response(&play_queue)
+}
+
+// Replaces the saved queue, an empty `entries` list deletes it.
+#[post("/queue")]
+async fn save_play_queue(
+    data: web::Data<AppState>,
+    query: web::Query<ListenerQuery>,
+    body: web::Json<PlayQueueRequest>,
+) -> impl Responder {
+    _save_play_queue(data, query, body.into_inner()).unwrap_or_else(error_response)
+}
+
+fn _save_play_queue(
+    data: web::Data<AppState>,
+    query: web::Query<ListenerQuery>,
+    body: PlayQueueRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let listener = query.listener()?;
+    let library = data.library()?;
+    if let Some(hash) = body
+        .entries
+        .iter()
+        .find(|hash| !library.audiofiles.contains_key(*hash))
+    {
+        return Err(ServiceError::NotFound(format!("Invalid hash {hash}")));
+    }
+    if let Some(current) = &body.current
+        && !body.entries.contains(current)
+    {
+        return Err(ServiceError::BadRequest(
+            "The current file is not in the queue".to_string(),
+        ));
+    }
+    match data.bookmarks.save_play_queue(
+        listener,
+        body.entries,
+        body.current,
+        body.position,
+        body.client,
+    )? {
+        Some(play_queue) => json_response(&play_queue),
+        None => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
 #[get("/ping")]
 async fn ping() -> impl Responder {
     if let Ok(body) = serde_json::to_vec(&PingResponse {
@@ -0,0 +1,164 @@
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub transcoding: TranscodingSettings,
+    pub loudness: LoudnessSettings,
+    pub watch: WatchSettings,
+    pub library: LibrarySettings,
+    pub artwork: ArtworkSettings,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct ArtworkSettings {
+    // Total size of cached thumbnails in MiB, 0 disables the cache.
+    pub cache_size: u64,
+}
+
+impl Default for ArtworkSettings {
+    fn default() -> Self {
+        ArtworkSettings { cache_size: 64 }
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct LibrarySettings {
+    pub ids: IdMode,
+}
+
+// What a file ID is the md5 of. Changing the mode re-hashes the library on the
+// next start and maps the previous IDs to the new ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdMode {
+    // The whole file, so editing a tag gives the file a new ID.
+    #[default]
+    File,
+    // Only the encoded audio, without tags and embedded artwork.
+    Audio,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct LoudnessSettings {
+    // Measures tracks without ReplayGain tags in the background after every scan.
+    pub analyze: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct WatchSettings {
+    pub enabled: bool,
+    // Milliseconds without new events before changes are applied.
+    pub debounce: u64,
+    // Seconds between walks of the base directory instead of inotify,
+    // for network mounts that do not deliver events.
+    pub poll_interval: Option<u64>,
+}
+
+impl Default for WatchSettings {
+    fn default() -> Self {
+        WatchSettings {
+            enabled: true,
+            debounce: 2000,
+            poll_interval: None,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub struct TranscodingSettings {
+    // Encoder command per output format, e.g. "mp3": ["lame", "-r", ...].
+    // Arguments may contain {bitrate} in kbit/s, {sample_rate} and {channels},
+    // the encoder reads signed 16-bit little-endian PCM from stdin
+    // and writes the encoded stream to stdout.
+    pub encoders: std::collections::HashMap<String, Vec<String>>,
+    pub default_format: String,
+    pub default_bitrate: u32,
+    // Total size of cached transcodes in MiB, 0 disables the cache.
+    pub cache_size: u64,
+    // Checked in order when a request names neither format nor bitrate.
+    pub profiles: Vec<ClientProfile>,
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(default)]
+pub struct ClientProfile {
+    pub name: String,
+    pub client: Option<String>,
+    // Case-insensitive, `*` matches any text.
+    pub user_agent: Option<String>,
+    pub user: Option<String>,
+    // "raw" passes the original file through.
+    pub format: Option<String>,
+    pub max_bit_rate: Option<u32>,
+}
+
+impl ClientProfile {
+    // Every criterion that is set has to match, a profile without any matches all requests.
+    pub fn matches(
+        &self,
+        client: Option<&str>,
+        user_agent: Option<&str>,
+        user: Option<&str>,
+    ) -> bool {
+        let equals = |expected: &Option<String>, actual: Option<&str>| {
+            expected
+                .as_deref()
+                .is_none_or(|expected| actual == Some(expected))
+        };
+        equals(&self.client, client)
+            && equals(&self.user, user)
+            && self.user_agent.as_deref().is_none_or(|pattern| {
+                user_agent.is_some_and(|user_agent| {
+                    wildcard_match(&pattern.to_lowercase(), &user_agent.to_lowercase())
+                })
+            })
+    }
+}
+
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+impl Default for TranscodingSettings {
+    fn default() -> Self {
+        TranscodingSettings {
+            encoders: std::collections::HashMap::new(),
+            default_format: "mp3".to_string(),
+            default_bitrate: 128,
+            cache_size: 1024,
+            profiles: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    IOError(std::io::Error),
+    SerdeJsonError(serde_json::Error),
+}
+
+impl Settings {
+    pub fn load(path: &std::path::Path) -> Result<Settings, SettingsError> {
+        let data = std::fs::read(path).map_err(SettingsError::IOError)?;
+        serde_json::from_slice(&data).map_err(SettingsError::SerdeJsonError)
+    }
+}
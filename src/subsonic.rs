@@ -0,0 +1,149 @@
+use crate::{AppState, hex_decode, hex_encode};
+use actix_web::error::{InternalError, QueryPayloadError};
+use actix_web::{FromRequest, Handler, HttpRequest, Resource, Responder, Scope, web};
+use md5::{Digest, Md5};
+use response::{Body, SubsonicError, render};
+
+pub mod browsing;
+pub mod library;
+pub mod lists;
+pub mod media;
+pub mod model;
+pub mod response;
+pub mod scanning;
+pub mod searching;
+
+pub const API_VERSION: &str = "1.16.1";
+
+#[derive(Default, serde::Deserialize)]
+pub struct SubsonicParams {
+    pub u: Option<String>,
+    pub p: Option<String>,
+    pub t: Option<String>,
+    pub s: Option<String>,
+    pub v: Option<String>,
+    pub c: Option<String>,
+    pub f: Option<String>,
+    pub callback: Option<String>,
+}
+
+pub fn scope() -> Scope {
+    web::scope("/rest")
+        .app_data(web::QueryConfig::default().error_handler(invalid_query))
+        .service(view("ping", ping))
+        .service(view("getMusicFolders", browsing::get_music_folders))
+        .service(view("getIndexes", browsing::get_indexes))
+        .service(view("getMusicDirectory", browsing::get_music_directory))
+        .service(view("getArtists", library::get_artists))
+        .service(view("getArtist", library::get_artist))
+        .service(view("getAlbum", library::get_album))
+        .service(view("getSong", library::get_song))
+        .service(view("stream", media::stream))
+        .service(view("download", media::download))
+        .service(view("getCoverArt", media::get_cover_art))
+        .service(view("getLyricsBySongId", media::get_lyrics_by_song_id))
+        .service(view("search3", searching::search3))
+        .service(view("getRandomSongs", lists::get_random_songs))
+        .service(view("startScan", scanning::start_scan_view))
+        .service(view("getScanStatus", scanning::get_scan_status))
+}
+
+// Parameters of the wrong type are reported as a Subsonic error in the
+// requested format instead of a plain 400.
+fn invalid_query(err: QueryPayloadError, req: &HttpRequest) -> actix_web::Error {
+    let params = web::Query::<SubsonicParams>::from_query(req.query_string())
+        .map(|params| params.into_inner())
+        .unwrap_or_default();
+    let response = render(
+        &params,
+        Err(SubsonicError::InvalidParameter(err.to_string())),
+    );
+    InternalError::from_response(err, response).into()
+}
+
+fn view<F, Args>(name: &str, handler: F) -> Resource
+where
+    F: Handler<Args>,
+    Args: FromRequest + 'static,
+    F::Output: Responder + 'static,
+{
+    web::resource([format!("/{name}"), format!("/{name}.view")])
+        .route(web::get().to(handler.clone()))
+        .route(web::post().to(handler))
+}
+
+pub fn authenticate(data: &AppState, params: &SubsonicParams) -> Result<(), SubsonicError> {
+    if let Some(version) = &params.v {
+        check_version(version)?;
+    }
+    if data.users.is_empty() {
+        return Ok(());
+    }
+
+    let username = params
+        .u
+        .as_ref()
+        .ok_or(SubsonicError::MissingParameter("u".to_string()))?;
+    let password = data
+        .users
+        .get(username)
+        .ok_or(SubsonicError::WrongCredentials)?;
+
+    if let Some(p) = &params.p {
+        let provided = match p.strip_prefix("enc:") {
+            Some(hex) => hex_decode(hex)
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or(SubsonicError::WrongCredentials)?,
+            None => p.to_owned(),
+        };
+        if constant_time_eq(provided.as_bytes(), password.as_bytes()) {
+            return Ok(());
+        }
+        return Err(SubsonicError::WrongCredentials);
+    }
+
+    match (&params.t, &params.s) {
+        (Some(token), Some(salt)) => {
+            let mut hasher = Md5::new();
+            hasher.update(password.as_bytes());
+            hasher.update(salt.as_bytes());
+            let expected = hex_encode(hasher.finalize().to_vec());
+            if constant_time_eq(token.to_ascii_lowercase().as_bytes(), expected.as_bytes()) {
+                Ok(())
+            } else {
+                Err(SubsonicError::WrongCredentials)
+            }
+        }
+        (None, _) => Err(SubsonicError::MissingParameter("t".to_string())),
+        (_, None) => Err(SubsonicError::MissingParameter("s".to_string())),
+    }
+}
+
+// Takes the same time for every mismatch of equal length, so a password
+// cannot be guessed byte by byte from response times.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn check_version(client_version: &str) -> Result<(), SubsonicError> {
+    let parse = |version: &str| -> Vec<u32> {
+        version
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+    let client = parse(client_version);
+    let server = parse(API_VERSION);
+    let client_major = client.first().copied().unwrap_or(0);
+    if client_major < server[0] {
+        Err(SubsonicError::ClientTooOld)
+    } else if client_major > server[0] || client.get(1).copied().unwrap_or(0) > server[1] {
+        Err(SubsonicError::ServerTooOld)
+    } else {
+        Ok(())
+    }
+}
+
+async fn ping(data: web::Data<AppState>, params: web::Query<SubsonicParams>) -> impl Responder {
+    render(&params, authenticate(&data, &params).map(|_| Body::Empty))
+}
@@ -0,0 +1,224 @@
+use crate::AppState;
+use crate::library::MUSIC_FOLDER_ID;
+use crate::services::ServiceError;
+use crate::subsonic::model::{Artist, Child, MusicFolder};
+use crate::subsonic::response::{Body, SubsonicError, render};
+use crate::subsonic::{SubsonicParams, authenticate};
+use actix_web::{Responder, web};
+
+pub const IGNORED_ARTICLES: &str = "The El La Los Las Le Les";
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrowsingParams {
+    pub id: Option<String>,
+    pub music_folder_id: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct MusicFolders {
+    #[serde(rename = "musicFolder")]
+    music_folder: Vec<MusicFolder>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Indexes {
+    last_modified: u128,
+    ignored_articles: String,
+    index: Vec<Index>,
+    child: Vec<Child>,
+}
+
+#[derive(serde::Serialize)]
+struct Index {
+    name: String,
+    artist: Vec<Artist>,
+}
+
+#[derive(serde::Serialize)]
+struct MusicDirectory {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
+    name: String,
+    child: Vec<Child>,
+}
+
+pub async fn get_music_folders(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+) -> impl Responder {
+    render(&params, _get_music_folders(&data, &params))
+}
+
+fn _get_music_folders(data: &AppState, params: &SubsonicParams) -> Result<Body, SubsonicError> {
+    authenticate(data, params)?;
+    let library = data.library()?;
+    let root = library
+        .directories
+        .get(&library.root)
+        .ok_or(ServiceError::ValuesExtractionError)?;
+
+    Body::new(
+        "musicFolders",
+        &MusicFolders {
+            music_folder: vec![MusicFolder {
+                id: MUSIC_FOLDER_ID,
+                name: root.name.clone(),
+            }],
+        },
+    )
+}
+
+pub async fn get_indexes(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    browsing: web::Query<BrowsingParams>,
+) -> impl Responder {
+    render(&params, _get_indexes(&data, &params, &browsing))
+}
+
+fn _get_indexes(
+    data: &AppState,
+    params: &SubsonicParams,
+    browsing: &BrowsingParams,
+) -> Result<Body, SubsonicError> {
+    authenticate(data, params)?;
+    check_music_folder(browsing.music_folder_id.as_deref())?;
+    let library = data.library()?;
+    let root = library
+        .directories
+        .get(&library.root)
+        .ok_or(ServiceError::ValuesExtractionError)?;
+
+    let mut index: Vec<Index> = vec![];
+    for dir in root
+        .directories
+        .iter()
+        .filter_map(|id| library.directories.get(id))
+    {
+        let letter = index_letter(&dir.name);
+        let artist = Artist {
+            id: dir.id.clone(),
+            name: dir.name.clone(),
+        };
+        match index.iter_mut().find(|i| i.name == letter) {
+            Some(i) => i.artist.push(artist),
+            None => index.push(Index {
+                name: letter,
+                artist: vec![artist],
+            }),
+        }
+    }
+    index.sort_by(|a, b| a.name.cmp(&b.name));
+    for i in index.iter_mut() {
+        i.artist
+            .sort_by_key(|artist| strip_article(&artist.name).to_lowercase());
+    }
+
+    let child = root
+        .files
+        .iter()
+        .filter_map(|hash| {
+            Some(Child::from_file(
+                &library,
+                hash,
+                library.audiofiles.get(hash)?,
+            ))
+        })
+        .collect();
+
+    Body::new(
+        "indexes",
+        &Indexes {
+            last_modified: library
+                .last_modified
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or(0),
+            ignored_articles: IGNORED_ARTICLES.to_string(),
+            index,
+            child,
+        },
+    )
+}
+
+pub async fn get_music_directory(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    browsing: web::Query<BrowsingParams>,
+) -> impl Responder {
+    render(&params, _get_music_directory(&data, &params, &browsing))
+}
+
+fn _get_music_directory(
+    data: &AppState,
+    params: &SubsonicParams,
+    browsing: &BrowsingParams,
+) -> Result<Body, SubsonicError> {
+    authenticate(data, params)?;
+    let id = browsing
+        .id
+        .as_ref()
+        .ok_or(SubsonicError::MissingParameter("id".to_string()))?;
+    let library = data.library()?;
+    let dir = library
+        .directories
+        .get(id)
+        .ok_or(SubsonicError::NotFound("Directory not found".to_string()))?;
+
+    let mut child: Vec<Child> = dir
+        .directories
+        .iter()
+        .filter_map(|id| library.directories.get(id))
+        .map(Child::from_directory)
+        .collect();
+    child.extend(dir.files.iter().filter_map(|hash| {
+        Some(Child::from_file(
+            &library,
+            hash,
+            library.audiofiles.get(hash)?,
+        ))
+    }));
+
+    Body::new(
+        "directory",
+        &MusicDirectory {
+            id: dir.id.clone(),
+            parent: dir.parent.clone(),
+            name: dir.name.clone(),
+            child,
+        },
+    )
+}
+
+pub fn check_music_folder(music_folder_id: Option<&str>) -> Result<(), SubsonicError> {
+    match music_folder_id {
+        Some(id) if id != MUSIC_FOLDER_ID.to_string() => Err(SubsonicError::NotFound(
+            "Music folder not found".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+pub fn strip_article(name: &str) -> &str {
+    IGNORED_ARTICLES
+        .split(' ')
+        .find_map(|article| {
+            let prefix = name.get(..article.len() + 1)?;
+            if prefix.eq_ignore_ascii_case(&format!("{article} ")) {
+                name.get(article.len() + 1..)
+            } else {
+                None
+            }
+        })
+        .unwrap_or(name)
+}
+
+pub fn index_letter(name: &str) -> String {
+    match strip_article(name).chars().next() {
+        Some(c) if c.is_alphabetic() => c.to_uppercase().collect(),
+        _ => "#".to_string(),
+    }
+}
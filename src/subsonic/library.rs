@@ -0,0 +1,187 @@
+use crate::AppState;
+use crate::subsonic::browsing::{
+    BrowsingParams, IGNORED_ARTICLES, check_music_folder, index_letter, strip_article,
+};
+use crate::subsonic::model::{AlbumID3, ArtistID3, Child};
+use crate::subsonic::response::{Body, SubsonicError, render};
+use crate::subsonic::{SubsonicParams, authenticate};
+use actix_web::{Responder, web};
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Artists {
+    ignored_articles: String,
+    index: Vec<IndexID3>,
+}
+
+#[derive(serde::Serialize)]
+struct IndexID3 {
+    name: String,
+    artist: Vec<ArtistID3>,
+}
+
+#[derive(serde::Serialize)]
+struct ArtistWithAlbums {
+    #[serde(flatten)]
+    artist: ArtistID3,
+    album: Vec<AlbumID3>,
+}
+
+#[derive(serde::Serialize)]
+struct AlbumWithSongs {
+    #[serde(flatten)]
+    album: AlbumID3,
+    song: Vec<Child>,
+}
+
+pub async fn get_artists(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    browsing: web::Query<BrowsingParams>,
+) -> impl Responder {
+    render(&params, _get_artists(&data, &params, &browsing))
+}
+
+fn _get_artists(
+    data: &AppState,
+    params: &SubsonicParams,
+    browsing: &BrowsingParams,
+) -> Result<Body, SubsonicError> {
+    authenticate(data, params)?;
+    check_music_folder(browsing.music_folder_id.as_deref())?;
+    let library = data.library()?;
+
+    let mut index: Vec<IndexID3> = vec![];
+    for artist in library.artists.values() {
+        let letter = index_letter(&artist.name);
+        let artist = ArtistID3::new(&library, artist);
+        match index.iter_mut().find(|i| i.name == letter) {
+            Some(i) => i.artist.push(artist),
+            None => index.push(IndexID3 {
+                name: letter,
+                artist: vec![artist],
+            }),
+        }
+    }
+    index.sort_by(|a, b| a.name.cmp(&b.name));
+    for i in index.iter_mut() {
+        i.artist
+            .sort_by_key(|artist| strip_article(&artist.name).to_lowercase());
+    }
+
+    Body::new(
+        "artists",
+        &Artists {
+            ignored_articles: IGNORED_ARTICLES.to_string(),
+            index,
+        },
+    )
+}
+
+pub async fn get_artist(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    browsing: web::Query<BrowsingParams>,
+) -> impl Responder {
+    render(&params, _get_artist(&data, &params, &browsing))
+}
+
+fn _get_artist(
+    data: &AppState,
+    params: &SubsonicParams,
+    browsing: &BrowsingParams,
+) -> Result<Body, SubsonicError> {
+    authenticate(data, params)?;
+    let id = browsing
+        .id
+        .as_ref()
+        .ok_or(SubsonicError::MissingParameter("id".to_string()))?;
+    let library = data.library()?;
+    let artist = library
+        .artists
+        .get(id)
+        .ok_or(SubsonicError::NotFound("Artist not found".to_string()))?;
+
+    Body::new(
+        "artist",
+        &ArtistWithAlbums {
+            artist: ArtistID3::new(&library, artist),
+            album: artist
+                .albums
+                .iter()
+                .filter_map(|id| library.albums.get(id))
+                .map(AlbumID3::new)
+                .collect(),
+        },
+    )
+}
+
+pub async fn get_album(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    browsing: web::Query<BrowsingParams>,
+) -> impl Responder {
+    render(&params, _get_album(&data, &params, &browsing))
+}
+
+fn _get_album(
+    data: &AppState,
+    params: &SubsonicParams,
+    browsing: &BrowsingParams,
+) -> Result<Body, SubsonicError> {
+    authenticate(data, params)?;
+    let id = browsing
+        .id
+        .as_ref()
+        .ok_or(SubsonicError::MissingParameter("id".to_string()))?;
+    let library = data.library()?;
+    let album = library
+        .albums
+        .get(id)
+        .ok_or(SubsonicError::NotFound("Album not found".to_string()))?;
+
+    Body::new(
+        "album",
+        &AlbumWithSongs {
+            album: AlbumID3::new(album),
+            song: album
+                .songs
+                .iter()
+                .filter_map(|hash| {
+                    Some(Child::from_file(
+                        &library,
+                        hash,
+                        library.audiofiles.get(hash)?,
+                    ))
+                })
+                .collect(),
+        },
+    )
+}
+
+pub async fn get_song(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    browsing: web::Query<BrowsingParams>,
+) -> impl Responder {
+    render(&params, _get_song(&data, &params, &browsing))
+}
+
+fn _get_song(
+    data: &AppState,
+    params: &SubsonicParams,
+    browsing: &BrowsingParams,
+) -> Result<Body, SubsonicError> {
+    authenticate(data, params)?;
+    let id = browsing
+        .id
+        .as_ref()
+        .ok_or(SubsonicError::MissingParameter("id".to_string()))?;
+    let library = data.library()?;
+    let path = library
+        .audiofiles
+        .get(id)
+        .ok_or(SubsonicError::NotFound("Song not found".to_string()))?;
+
+    Body::new("song", &Child::from_file(&library, id, path))
+}
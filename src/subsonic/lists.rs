@@ -0,0 +1,44 @@
+use crate::AppState;
+use crate::random::{RandomFilter, random_songs};
+use crate::subsonic::model::Child;
+use crate::subsonic::response::{Body, SubsonicError, render};
+use crate::subsonic::{SubsonicParams, authenticate};
+use actix_web::{Responder, web};
+
+#[derive(serde::Serialize)]
+struct Songs {
+    song: Vec<Child>,
+}
+
+pub async fn get_random_songs(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    filter: web::Query<RandomFilter>,
+) -> impl Responder {
+    render(&params, _get_random_songs(&data, &params, &filter))
+}
+
+fn _get_random_songs(
+    data: &AppState,
+    params: &SubsonicParams,
+    filter: &RandomFilter,
+) -> Result<Body, SubsonicError> {
+    authenticate(data, params)?;
+    let library = data.library()?;
+
+    Body::new(
+        "randomSongs",
+        &Songs {
+            song: random_songs(&library, filter)
+                .iter()
+                .filter_map(|hash| {
+                    Some(Child::from_file(
+                        &library,
+                        hash,
+                        library.audiofiles.get(hash)?,
+                    ))
+                })
+                .collect(),
+        },
+    )
+}
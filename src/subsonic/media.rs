@@ -0,0 +1,287 @@
+use crate::artwork::{ArtworkError, cover_art};
+use crate::conditional::Validators;
+use crate::lyrics::read_lyrics;
+use crate::range::{Source, respond};
+use crate::seek::seek;
+use crate::services::ServiceError;
+use crate::subsonic::response::{Body, SubsonicError, render};
+use crate::subsonic::{SubsonicParams, authenticate};
+use crate::transcode::{EstimatedBody, estimated_size, negotiate, requested};
+use crate::transcode_cache::transcoded_response;
+use crate::{AppState, extension_to_mime};
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, web};
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamParams {
+    pub id: Option<String>,
+    pub max_bit_rate: Option<u32>,
+    pub format: Option<String>,
+    pub time_offset: Option<f64>,
+    #[serde(default)]
+    pub estimate_content_length: bool,
+}
+
+pub async fn stream(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    stream: web::Query<StreamParams>,
+) -> HttpResponse {
+    _stream(&req, &data, &params, &stream)
+        .await
+        .unwrap_or_else(|err| render(&params, Err(err)))
+}
+
+async fn _stream(
+    req: &HttpRequest,
+    data: &AppState,
+    params: &SubsonicParams,
+    stream: &StreamParams,
+) -> Result<HttpResponse, SubsonicError> {
+    authenticate(data, params)?;
+    let path = find_file(data, stream.id.as_deref())?;
+    let mime = path
+        .extension()
+        .and_then(extension_to_mime)
+        .ok_or(ServiceError::ValuesExtractionError)?;
+
+    let (bitrate, duration) = data
+        .library()?
+        .tracks
+        .get(stream.id.as_deref().unwrap_or_default())
+        .map_or((None, 0), |tags| (tags.bitrate, tags.duration));
+    let transcoding = &data.settings.transcoding;
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|value| value.to_str().ok());
+    let (format, max_bit_rate) = requested(
+        transcoding,
+        stream.format.as_deref(),
+        stream.max_bit_rate,
+        params.c.as_deref(),
+        user_agent,
+        params.u.as_deref(),
+    );
+    if let Some(profile) = negotiate(transcoding, &path, bitrate, format, max_bit_rate)? {
+        let hash = stream.id.as_deref().unwrap_or_default();
+        let response =
+            transcoded_response(data, req, hash, &path, profile, stream.time_offset).await?;
+        // Cached transcodes already have their real length.
+        let remaining = duration as f64 - stream.time_offset.unwrap_or(0.0);
+        return Ok(match estimated_size(profile, remaining) {
+            Some(length)
+                if stream.estimate_content_length
+                    && response.status() == StatusCode::OK
+                    && response.body().size() == BodySize::Stream =>
+            {
+                response
+                    .map_body(|_, body| EstimatedBody::new(body, length))
+                    .map_into_boxed_body()
+            }
+            _ => response,
+        });
+    }
+
+    let seek = stream.time_offset.and_then(|offset| seek(&path, offset));
+    let mut validators = Validators::file(data, stream.id.as_deref().unwrap_or_default(), &path);
+    // A seeked body differs from the file, so it has its own ETag for If-Range,
+    // the modification date alone would match both.
+    if let Some(seek) = &seek {
+        validators = validators.with_suffix(&format!("at{:x}", seek.offset));
+        validators.last_modified = None;
+    }
+    let mut response = match validators.response(req) {
+        Ok(response) => response,
+        Err(not_modified) => return Ok(not_modified),
+    };
+    response.insert_header(("Content-Disposition", content_disposition("inline", &path)));
+    respond(
+        req,
+        response,
+        Source::seeked(&path, seek),
+        &mime,
+        &validators,
+    )
+    .map_err(|_| ServiceError::ValuesExtractionError.into())
+}
+
+pub async fn download(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    stream: web::Query<StreamParams>,
+) -> HttpResponse {
+    _download(&req, &data, &params, &stream).unwrap_or_else(|err| render(&params, Err(err)))
+}
+
+fn _download(
+    req: &HttpRequest,
+    data: &AppState,
+    params: &SubsonicParams,
+    stream: &StreamParams,
+) -> Result<HttpResponse, SubsonicError> {
+    authenticate(data, params)?;
+    let path = find_file(data, stream.id.as_deref())?;
+    let mime = path
+        .extension()
+        .and_then(extension_to_mime)
+        .ok_or(ServiceError::ValuesExtractionError)?;
+
+    let mut response = HttpResponse::Ok();
+    response.insert_header((
+        "Content-Disposition",
+        content_disposition("attachment", &path),
+    ));
+    let validators = Validators::file(data, stream.id.as_deref().unwrap_or_default(), &path);
+    respond(req, response, Source::new(&path), &mime, &validators)
+        .map_err(|_| ServiceError::ValuesExtractionError.into())
+}
+
+#[derive(serde::Deserialize)]
+pub struct CoverArtParams {
+    pub id: Option<String>,
+    pub size: Option<u32>,
+}
+
+pub async fn get_cover_art(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    cover_art: web::Query<CoverArtParams>,
+) -> HttpResponse {
+    _get_cover_art(&data, &params, &cover_art).unwrap_or_else(|err| render(&params, Err(err)))
+}
+
+fn _get_cover_art(
+    data: &AppState,
+    params: &SubsonicParams,
+    cover_art_params: &CoverArtParams,
+) -> Result<HttpResponse, SubsonicError> {
+    authenticate(data, params)?;
+    let id = cover_art_params
+        .id
+        .as_ref()
+        .ok_or(SubsonicError::MissingParameter("id".to_string()))?;
+    let hash = data
+        .library()?
+        .cover_art_track(id)
+        .ok_or(SubsonicError::NotFound("Cover art not found".to_string()))?;
+    let path = find_file(data, Some(&hash))?;
+
+    let version = data.file_version(&hash, &path);
+    let cache_size = data.settings.artwork.cache_size * 1024 * 1024;
+    match cover_art(
+        &data.cache_dir,
+        cache_size,
+        &version,
+        &path,
+        cover_art_params.size,
+    ) {
+        Ok(artwork) => Ok(HttpResponse::Ok()
+            .content_type(artwork.mime)
+            .body(artwork.data)),
+        Err(ArtworkError::NotFound) => {
+            Err(SubsonicError::NotFound("Cover art not found".to_string()))
+        }
+        Err(err) => Err(SubsonicError::Generic(format!("{err:?}"))),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct LyricsParams {
+    pub id: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LyricsList {
+    structured_lyrics: Vec<StructuredLyrics>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StructuredLyrics {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    display_title: Option<String>,
+    lang: String,
+    synced: bool,
+    line: Vec<Line>,
+}
+
+#[derive(serde::Serialize)]
+struct Line {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start: Option<u64>,
+    value: String,
+}
+
+pub async fn get_lyrics_by_song_id(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    lyrics: web::Query<LyricsParams>,
+) -> HttpResponse {
+    render(&params, _get_lyrics_by_song_id(&data, &params, &lyrics))
+}
+
+fn _get_lyrics_by_song_id(
+    data: &AppState,
+    params: &SubsonicParams,
+    lyrics_params: &LyricsParams,
+) -> Result<Body, SubsonicError> {
+    authenticate(data, params)?;
+    let path = find_file(data, lyrics_params.id.as_deref())?;
+    let library = data.library()?;
+    let tags = library
+        .tracks
+        .get(lyrics_params.id.as_deref().unwrap_or_default());
+
+    let structured_lyrics = read_lyrics(&path)
+        .into_iter()
+        .map(|lyrics| StructuredLyrics {
+            display_artist: tags.and_then(|tags| tags.artist.clone()),
+            display_title: tags.and_then(|tags| tags.title.clone()),
+            lang: lyrics.language.unwrap_or_else(|| "und".to_string()),
+            synced: lyrics.synced,
+            line: lyrics
+                .lines
+                .into_iter()
+                .map(|line| Line {
+                    start: line.start,
+                    value: line.text,
+                })
+                .collect(),
+        })
+        .collect();
+    Body::new("lyricsList", &LyricsList { structured_lyrics })
+}
+
+fn find_file(data: &AppState, id: Option<&str>) -> Result<std::path::PathBuf, SubsonicError> {
+    let id = id.ok_or(SubsonicError::MissingParameter("id".to_string()))?;
+    data.library()?
+        .audiofiles
+        .get(id)
+        .cloned()
+        .ok_or(SubsonicError::NotFound("Song not found".to_string()))
+}
+
+pub fn content_disposition(disposition: &str, path: &std::path::Path) -> String {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect();
+    format!("{disposition}; filename*=UTF-8''{encoded}")
+}
@@ -0,0 +1,186 @@
+use crate::extension_to_mime;
+use crate::library::{Album, Directory, Library};
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MusicFolder {
+    pub id: u32,
+    pub name: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Artist {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtistID3 {
+    pub id: String,
+    pub name: String,
+    pub album_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover_art: Option<String>,
+}
+
+impl ArtistID3 {
+    pub fn new(library: &Library, artist: &crate::library::Artist) -> ArtistID3 {
+        ArtistID3 {
+            id: artist.id.clone(),
+            name: artist.name.clone(),
+            album_count: artist.albums.len(),
+            cover_art: artist
+                .albums
+                .iter()
+                .find_map(|id| library.albums.get(id)?.cover_art.clone()),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlbumID3 {
+    pub id: String,
+    pub name: String,
+    pub artist: String,
+    pub artist_id: String,
+    pub song_count: usize,
+    pub duration: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover_art: Option<String>,
+}
+
+impl AlbumID3 {
+    pub fn new(album: &Album) -> AlbumID3 {
+        AlbumID3 {
+            id: album.id.clone(),
+            name: album.name.clone(),
+            artist: album.artist.clone(),
+            artist_id: album.artist_id.clone(),
+            song_count: album.songs.len(),
+            duration: album.duration,
+            year: album.year,
+            genre: album.genre.clone(),
+            cover_art: album.cover_art.clone(),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Child {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    pub is_dir: bool,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub track: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disc_number: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genre: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover_art: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bit_rate: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suffix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artist_id: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub media_kind: Option<String>,
+}
+
+impl Child {
+    pub fn from_directory(dir: &Directory) -> Child {
+        Child {
+            id: dir.id.clone(),
+            parent: dir.parent.clone(),
+            is_dir: true,
+            title: dir.name.clone(),
+            album: None,
+            artist: None,
+            track: None,
+            disc_number: None,
+            year: None,
+            genre: None,
+            cover_art: None,
+            size: None,
+            duration: None,
+            bit_rate: None,
+            path: None,
+            suffix: None,
+            content_type: None,
+            media_type: None,
+            album_id: None,
+            artist_id: None,
+            media_kind: None,
+        }
+    }
+
+    pub fn from_file(library: &Library, hash: &str, path: &std::path::Path) -> Child {
+        let tags = library.tracks.get(hash);
+        let album = library
+            .track_albums
+            .get(hash)
+            .and_then(|id| library.albums.get(id));
+        Child {
+            id: hash.to_owned(),
+            parent: library.file_parents.get(hash).cloned(),
+            is_dir: false,
+            title: tags
+                .and_then(|t| t.title.clone())
+                .or_else(|| {
+                    path.file_stem()
+                        .map(|stem| stem.to_string_lossy().to_string())
+                })
+                .unwrap_or_default(),
+            album: album.map(|a| a.name.clone()),
+            artist: tags
+                .and_then(|t| t.artist.clone())
+                .or_else(|| album.map(|a| a.artist.clone())),
+            track: tags.and_then(|t| t.track),
+            disc_number: tags.and_then(|t| t.disc),
+            year: tags.and_then(|t| t.year),
+            genre: tags.and_then(|t| t.genre.clone()),
+            cover_art: tags.filter(|t| t.has_artwork).map(|_| hash.to_owned()),
+            size: tags.map(|t| t.size),
+            duration: tags.map(|t| t.duration),
+            bit_rate: tags.and_then(|t| t.bitrate),
+            path: Some(library.relative_path(path)),
+            suffix: path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase()),
+            content_type: path.extension().and_then(extension_to_mime),
+            media_type: Some("song".to_string()),
+            album_id: album.map(|a| a.id.clone()),
+            artist_id: album.map(|a| a.artist_id.clone()),
+            media_kind: Some("music".to_string()),
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::services::ServiceError;
+use crate::subsonic::{API_VERSION, SubsonicParams};
+use crate::transcode::TranscodeError;
+use actix_web::HttpResponse;
+
+const XMLNS: &str = "http://subsonic.org/restapi";
+
+pub enum Format {
+    Xml,
+    Json,
+    Jsonp(String),
+}
+
+impl Format {
+    pub fn from_params(params: &SubsonicParams) -> Result<Format, SubsonicError> {
+        match params.f.as_deref() {
+            None | Some("xml") => Ok(Format::Xml),
+            Some("json") => Ok(Format::Json),
+            Some("jsonp") => params
+                .callback
+                .as_ref()
+                .filter(|callback| is_valid_callback(callback))
+                .map(|callback| Format::Jsonp(callback.to_owned()))
+                .ok_or(SubsonicError::MissingParameter("callback".to_string())),
+            Some(_) => Ok(Format::Xml),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SubsonicError {
+    Generic(String),
+    MissingParameter(String),
+    InvalidParameter(String),
+    ClientTooOld,
+    ServerTooOld,
+    WrongCredentials,
+    NotAuthorized(String),
+    NotFound(String),
+}
+
+impl SubsonicError {
+    pub fn code(&self) -> u32 {
+        match self {
+            SubsonicError::Generic(_) => 0,
+            SubsonicError::MissingParameter(_) | SubsonicError::InvalidParameter(_) => 10,
+            SubsonicError::ClientTooOld => 20,
+            SubsonicError::ServerTooOld => 30,
+            SubsonicError::WrongCredentials => 40,
+            SubsonicError::NotAuthorized(_) => 50,
+            SubsonicError::NotFound(_) => 70,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            SubsonicError::Generic(message) | SubsonicError::InvalidParameter(message) => {
+                message.to_owned()
+            }
+            SubsonicError::MissingParameter(param) => {
+                format!("Required parameter is missing: {param}")
+            }
+            SubsonicError::ClientTooOld => {
+                "Incompatible Subsonic REST protocol version. Client must upgrade.".to_string()
+            }
+            SubsonicError::ServerTooOld => {
+                "Incompatible Subsonic REST protocol version. Server must upgrade.".to_string()
+            }
+            SubsonicError::WrongCredentials => "Wrong username or password".to_string(),
+            SubsonicError::NotAuthorized(message) => message.to_owned(),
+            SubsonicError::NotFound(message) => message.to_owned(),
+        }
+    }
+}
+
+impl From<ServiceError> for SubsonicError {
+    fn from(err: ServiceError) -> Self {
+        match err {
+            ServiceError::NotFound(message) => SubsonicError::NotFound(message),
+            ServiceError::BadRequest(message) => SubsonicError::InvalidParameter(message),
+            err => {
+                eprintln!("{err:?}");
+                SubsonicError::Generic("Internal Server Error".to_string())
+            }
+        }
+    }
+}
+
+impl From<serde_json::Error> for SubsonicError {
+    fn from(err: serde_json::Error) -> Self {
+        ServiceError::from(err).into()
+    }
+}
+
+impl From<TranscodeError> for SubsonicError {
+    fn from(err: TranscodeError) -> Self {
+        match err {
+            TranscodeError::UnsupportedFormat(format) => {
+                SubsonicError::InvalidParameter(format!("Unsupported format: {format}"))
+            }
+            TranscodeError::NoEncoder(format) => {
+                SubsonicError::Generic(format!("No encoder configured for {}", format.name()))
+            }
+            TranscodeError::Undecodable => {
+                SubsonicError::Generic("The file cannot be decoded for transcoding".to_string())
+            }
+            err => {
+                eprintln!("{err:?}");
+                SubsonicError::Generic("Internal Server Error".to_string())
+            }
+        }
+    }
+}
+
+pub enum Body {
+    Empty,
+    Element(&'static str, serde_json::Value),
+}
+
+impl Body {
+    pub fn new<T: serde::Serialize>(name: &'static str, value: &T) -> Result<Body, SubsonicError> {
+        Ok(Body::Element(name, serde_json::to_value(value)?))
+    }
+}
+
+pub fn render(params: &SubsonicParams, result: Result<Body, SubsonicError>) -> HttpResponse {
+    let (format, result) = match Format::from_params(params) {
+        Ok(format) => (format, result),
+        Err(err) => (Format::Json, Err(err)),
+    };
+
+    let mut response = serde_json::Map::new();
+    let status = if result.is_ok() { "ok" } else { "failed" };
+    response.insert("status".to_string(), status.into());
+    response.insert("version".to_string(), API_VERSION.into());
+    response.insert("type".to_string(), env!("CARGO_PKG_NAME").into());
+    response.insert(
+        "serverVersion".to_string(),
+        env!("CARGO_PKG_VERSION").into(),
+    );
+    response.insert("openSubsonic".to_string(), true.into());
+    match result {
+        Ok(Body::Empty) => {}
+        Ok(Body::Element(name, value)) => {
+            response.insert(name.to_string(), value);
+        }
+        Err(err) => {
+            response.insert(
+                "error".to_string(),
+                serde_json::json!({ "code": err.code(), "message": err.message() }),
+            );
+        }
+    }
+    let response = serde_json::Value::Object(response);
+
+    match format {
+        Format::Xml => {
+            let mut body = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            write_xml_element(&mut body, "subsonic-response", &response, Some(XMLNS));
+            HttpResponse::Ok()
+                .content_type("text/xml; charset=utf-8")
+                .body(body)
+        }
+        Format::Json => HttpResponse::Ok()
+            .content_type("application/json; charset=utf-8")
+            .body(serde_json::json!({ "subsonic-response": response }).to_string()),
+        Format::Jsonp(callback) => HttpResponse::Ok()
+            .content_type("application/javascript; charset=utf-8")
+            .body(format!(
+                "{callback}({});",
+                serde_json::json!({ "subsonic-response": response })
+            )),
+    }
+}
+
+// Scalars become attributes, nested objects and arrays become child elements
+// and a scalar stored under "value" becomes the element text.
+fn write_xml_element(out: &mut String, name: &str, value: &serde_json::Value, xmlns: Option<&str>) {
+    out.push('<');
+    out.push_str(name);
+    if let Some(xmlns) = xmlns {
+        out.push_str(&format!(" xmlns=\"{xmlns}\""));
+    }
+
+    let mut text = None;
+    let mut children = vec![];
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                match value {
+                    serde_json::Value::Null => {}
+                    serde_json::Value::Object(_) => children.push((key, value)),
+                    serde_json::Value::Array(items) => {
+                        children.extend(items.iter().map(|item| (key, item)))
+                    }
+                    scalar if key == "value" => text = scalar_to_string(scalar),
+                    scalar => {
+                        if let Some(scalar) = scalar_to_string(scalar) {
+                            out.push_str(&format!(" {key}=\"{}\"", xml_escape(&scalar)));
+                        }
+                    }
+                }
+            }
+        }
+        scalar => text = scalar_to_string(scalar),
+    }
+
+    if text.is_none() && children.is_empty() {
+        out.push_str("/>");
+        return;
+    }
+    out.push('>');
+    if let Some(text) = text {
+        out.push_str(&xml_escape(&text));
+    }
+    for (key, child) in children {
+        write_xml_element(out, key, child, None);
+    }
+    out.push_str(&format!("</{name}>"));
+}
+
+fn scalar_to_string(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) => Some(s.to_owned()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn is_valid_callback(callback: &str) -> bool {
+    !callback.is_empty()
+        && callback
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
@@ -0,0 +1,60 @@
+use crate::AppState;
+use crate::scan::{ScanStatus, start_scan};
+use crate::subsonic::response::{Body, SubsonicError, render};
+use crate::subsonic::{SubsonicParams, authenticate};
+use actix_web::{Responder, web};
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SubsonicScanStatus {
+    scanning: bool,
+    count: u64,
+    folder_count: u64,
+    hashed_count: u64,
+    reused_count: u64,
+    error_count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_scan: Option<u64>,
+}
+
+impl From<ScanStatus> for SubsonicScanStatus {
+    fn from(status: ScanStatus) -> Self {
+        SubsonicScanStatus {
+            scanning: status.scanning,
+            count: status.hashed + status.reused,
+            folder_count: status.directories,
+            hashed_count: status.hashed,
+            reused_count: status.reused,
+            error_count: status.errors,
+            last_scan: status.finished,
+        }
+    }
+}
+
+pub async fn start_scan_view(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+) -> impl Responder {
+    let result = authenticate(&data, &params).and_then(|_| {
+        start_scan(data.clone());
+        scan_status(&data)
+    });
+    render(&params, result)
+}
+
+pub async fn get_scan_status(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+) -> impl Responder {
+    render(
+        &params,
+        authenticate(&data, &params).and_then(|_| scan_status(&data)),
+    )
+}
+
+fn scan_status(data: &AppState) -> Result<Body, SubsonicError> {
+    Body::new(
+        "scanStatus",
+        &SubsonicScanStatus::from(data.scan_progress.status()),
+    )
+}
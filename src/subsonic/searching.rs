@@ -0,0 +1,76 @@
+use crate::AppState;
+use crate::subsonic::browsing::check_music_folder;
+use crate::subsonic::model::{AlbumID3, ArtistID3, Child};
+use crate::subsonic::response::{Body, SubsonicError, render};
+use crate::subsonic::{SubsonicParams, authenticate};
+use actix_web::{Responder, web};
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Search3Params {
+    pub query: Option<String>,
+    pub artist_count: Option<usize>,
+    pub artist_offset: Option<usize>,
+    pub album_count: Option<usize>,
+    pub album_offset: Option<usize>,
+    pub song_count: Option<usize>,
+    pub song_offset: Option<usize>,
+    pub music_folder_id: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct SearchResult3 {
+    artist: Vec<ArtistID3>,
+    album: Vec<AlbumID3>,
+    song: Vec<Child>,
+}
+
+pub async fn search3(
+    data: web::Data<AppState>,
+    params: web::Query<SubsonicParams>,
+    search: web::Query<Search3Params>,
+) -> impl Responder {
+    render(&params, _search3(&data, &params, &search))
+}
+
+fn _search3(
+    data: &AppState,
+    params: &SubsonicParams,
+    search: &Search3Params,
+) -> Result<Body, SubsonicError> {
+    authenticate(data, params)?;
+    check_music_folder(search.music_folder_id.as_deref())?;
+    let query = search
+        .query
+        .as_ref()
+        .ok_or(SubsonicError::MissingParameter("query".to_string()))?;
+    let library = data.library()?;
+    let matches = library.search.search(&library, query);
+
+    let page = |ids: Vec<String>, offset: Option<usize>, count: Option<usize>| {
+        ids.into_iter()
+            .skip(offset.unwrap_or(0))
+            .take(count.unwrap_or(20))
+    };
+
+    Body::new(
+        "searchResult3",
+        &SearchResult3 {
+            artist: page(matches.artists, search.artist_offset, search.artist_count)
+                .filter_map(|id| Some(ArtistID3::new(&library, library.artists.get(&id)?)))
+                .collect(),
+            album: page(matches.albums, search.album_offset, search.album_count)
+                .filter_map(|id| Some(AlbumID3::new(library.albums.get(&id)?)))
+                .collect(),
+            song: page(matches.songs, search.song_offset, search.song_count)
+                .filter_map(|id| {
+                    Some(Child::from_file(
+                        &library,
+                        &id,
+                        library.audiofiles.get(&id)?,
+                    ))
+                })
+                .collect(),
+        },
+    )
+}
@@ -0,0 +1,25 @@
+// A directory for the fixture files of one test, removed with its contents
+// when the test ends, whether it passed or not.
+pub struct TestDir {
+    path: std::path::PathBuf,
+}
+
+impl TestDir {
+    pub fn new(name: &str) -> TestDir {
+        let path =
+            std::env::temp_dir().join(format!("subsonic_vault-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+
+    pub fn join(&self, name: &str) -> std::path::PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,859 @@
+use crate::settings::TranscodingSettings;
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use actix_web::web::Bytes;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::task::{Context, Poll};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CODEC_TYPE_NULL, Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use tokio::sync::mpsc;
+
+const CHANNEL_CAPACITY: usize = 16;
+const MIN_BITRATE: u32 = 32;
+const MAX_BITRATE: u32 = 320;
+const PADDING_CHUNK: u64 = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Mp3,
+    Opus,
+    Aac,
+    Wav,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "mp3" => Some(Format::Mp3),
+            "opus" => Some(Format::Opus),
+            "aac" => Some(Format::Aac),
+            "wav" => Some(Format::Wav),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Mp3 => "mp3",
+            Format::Opus => "opus",
+            Format::Aac => "aac",
+            Format::Wav => "wav",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Format::Mp3 => "audio/mpeg",
+            Format::Opus => "audio/ogg",
+            Format::Aac => "audio/aac",
+            Format::Wav => "audio/wav",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Profile {
+    pub format: Format,
+    pub bitrate: u32,
+}
+
+#[derive(Debug)]
+pub enum TranscodeError {
+    UnsupportedFormat(String),
+    NoEncoder(Format),
+    // The built-in decoders cannot read the source, Opus for one.
+    Undecodable,
+    NoAudioTrack,
+    EncoderFailed(std::process::ExitStatus),
+    IOError(std::io::Error),
+    DecodeError(SymphoniaError),
+}
+
+impl From<std::io::Error> for TranscodeError {
+    fn from(err: std::io::Error) -> Self {
+        TranscodeError::IOError(err)
+    }
+}
+
+impl From<SymphoniaError> for TranscodeError {
+    fn from(err: SymphoniaError) -> Self {
+        TranscodeError::DecodeError(err)
+    }
+}
+
+// An explicit format or bitrate in the request wins, otherwise the first
+// client profile matching the client name, User-Agent or user decides.
+pub fn requested<'a>(
+    settings: &'a TranscodingSettings,
+    format: Option<&'a str>,
+    max_bit_rate: Option<u32>,
+    client: Option<&str>,
+    user_agent: Option<&str>,
+    user: Option<&str>,
+) -> (Option<&'a str>, Option<u32>) {
+    let format = format.filter(|format| !format.is_empty());
+    let max_bit_rate = max_bit_rate.filter(|max| *max > 0);
+    if format.is_some() || max_bit_rate.is_some() {
+        return (format, max_bit_rate);
+    }
+    settings
+        .profiles
+        .iter()
+        .find(|profile| profile.matches(client, user_agent, user))
+        .map_or((None, None), |profile| {
+            (profile.format.as_deref(), profile.max_bit_rate)
+        })
+}
+
+// Decides whether a file has to be transcoded for the requested format and
+// maximum bitrate in kbit/s. Returns None when the original can be served:
+// "raw" was requested, the file already has the wanted format and bitrate,
+// or the default format it would fall back to cannot be encoded or the file
+// cannot be decoded. Only a format that was asked for fails then.
+pub fn negotiate(
+    settings: &TranscodingSettings,
+    path: &std::path::Path,
+    bitrate: Option<u32>,
+    format: Option<&str>,
+    max_bit_rate: Option<u32>,
+) -> Result<Option<Profile>, TranscodeError> {
+    let max_bit_rate = max_bit_rate.filter(|max| *max > 0);
+    let fits = max_bit_rate.is_none_or(|max| bitrate.is_some_and(|bitrate| bitrate <= max));
+    let source = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(Format::from_name);
+
+    let (format, explicit) = match format.filter(|format| !format.is_empty()) {
+        Some("raw") => return Ok(None),
+        Some(name) => (
+            Format::from_name(name).ok_or(TranscodeError::UnsupportedFormat(name.to_owned()))?,
+            true,
+        ),
+        None if fits => return Ok(None),
+        None => match Format::from_name(&settings.default_format) {
+            Some(format) => (format, false),
+            None => return Ok(None),
+        },
+    };
+    if source == Some(format) && fits {
+        return Ok(None);
+    }
+    if format != Format::Wav && !settings.encoders.contains_key(format.name()) {
+        return match explicit {
+            true => Err(TranscodeError::NoEncoder(format)),
+            false => Ok(None),
+        };
+    }
+    if !decodable(path) {
+        return match explicit {
+            true => Err(TranscodeError::Undecodable),
+            false => Ok(None),
+        };
+    }
+
+    Ok(Some(Profile {
+        format,
+        bitrate: bitrate_for(settings, max_bit_rate),
+    }))
+}
+
+// Whether the built-in decoders can read the file, which only takes its headers.
+pub fn decodable(path: &std::path::Path) -> bool {
+    Source::open(path, Position::Start).is_ok()
+}
+
+pub fn bitrate_for(settings: &TranscodingSettings, max_bit_rate: Option<u32>) -> u32 {
+    max_bit_rate
+        .filter(|max| *max > 0)
+        .unwrap_or(settings.default_bitrate)
+        .clamp(MIN_BITRATE, MAX_BITRATE)
+}
+
+// Receives the encoded output chunk by chunk while the file is still being
+// transcoded, so playback can start right away.
+pub struct TranscodeBody {
+    prefix: Option<Bytes>,
+    receiver: mpsc::Receiver<Bytes>,
+}
+
+impl TranscodeBody {
+    pub fn with_prefix(self, prefix: Vec<u8>) -> TranscodeBody {
+        TranscodeBody {
+            prefix: Some(Bytes::from(prefix)),
+            ..self
+        }
+    }
+}
+
+impl MessageBody for TranscodeBody {
+    type Error = std::io::Error;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        if let Some(prefix) = this.prefix.take() {
+            return Poll::Ready(Some(Ok(prefix)));
+        }
+        this.receiver.poll_recv(cx).map(|chunk| chunk.map(Ok))
+    }
+}
+
+// The size of a transcode from its bitrate and duration, for clients that ask
+// for a Content-Length up front. WAV has no bitrate to go by.
+pub fn estimated_size(profile: Profile, duration: f64) -> Option<u64> {
+    (profile.format != Format::Wav)
+        .then(|| (duration.max(0.0) * profile.bitrate as f64 * 1000.0 / 8.0) as u64)
+}
+
+// Sends exactly `length` bytes of a streamed body: output beyond it is cut off
+// and a shorter stream is padded with zeros, which decoders skip as junk after
+// the last frame.
+pub struct EstimatedBody {
+    body: BoxBody,
+    length: u64,
+    remaining: u64,
+    finished: bool,
+}
+
+impl EstimatedBody {
+    pub fn new(body: BoxBody, length: u64) -> EstimatedBody {
+        EstimatedBody {
+            body,
+            length,
+            remaining: length,
+            finished: false,
+        }
+    }
+}
+
+impl MessageBody for EstimatedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        BodySize::Sized(self.length)
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        if this.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        if !this.finished {
+            match Pin::new(&mut this.body).poll_next(cx) {
+                Poll::Ready(Some(Ok(mut chunk))) => {
+                    chunk.truncate(this.remaining.min(chunk.len() as u64) as usize);
+                    this.remaining -= chunk.len() as u64;
+                    return Poll::Ready(Some(Ok(chunk)));
+                }
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err))),
+                Poll::Ready(None) => this.finished = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let padding = this.remaining.min(PADDING_CHUNK);
+        this.remaining -= padding;
+        Poll::Ready(Some(Ok(Bytes::from(vec![0; padding as usize]))))
+    }
+}
+
+// Forwards written bytes to the response, fails once the client is gone.
+struct ChannelWriter {
+    sender: mpsc::Sender<Bytes>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.sender
+            .blocking_send(Bytes::copy_from_slice(buf))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Position {
+    Start,
+    // Starts at the closest packet, which is enough for progressive streams.
+    Offset(f64),
+    // Exactly `length` seconds from `start`, so consecutive segments line up.
+    Segment { start: f64, length: f64 },
+}
+
+impl Position {
+    pub fn from_offset(time_offset: Option<f64>) -> Position {
+        match time_offset {
+            Some(offset) if offset > 0.0 => Position::Offset(offset),
+            _ => Position::Start,
+        }
+    }
+}
+
+pub struct Source {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    skip: f64,
+    length: Option<f64>,
+    frames_left: Option<u64>,
+}
+
+impl Source {
+    pub fn open(path: &std::path::Path, position: Position) -> Result<Source, TranscodeError> {
+        let file = std::fs::File::open(path)?;
+        let stream = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+            hint.with_extension(ext);
+        }
+        let mut format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                stream,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )?
+            .format;
+        let track = format
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(TranscodeError::NoAudioTrack)?;
+        let track_id = track.id;
+        let time_base = track.codec_params.time_base;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+
+        let (mode, start, length) = match position {
+            Position::Start => (SeekMode::Coarse, 0.0, None),
+            Position::Offset(offset) => (SeekMode::Coarse, offset, None),
+            Position::Segment { start, length } => (SeekMode::Accurate, start, Some(length)),
+        };
+        let mut skip = 0.0;
+        if start > 0.0 {
+            let seeked = format.seek(
+                mode,
+                SeekTo::Time {
+                    time: start.into(),
+                    track_id: Some(track_id),
+                },
+            )?;
+            decoder.reset();
+            // An accurate seek lands on the packet containing the target,
+            // the samples before it are dropped after decoding.
+            if mode == SeekMode::Accurate
+                && let Some(time_base) = time_base
+            {
+                let time = time_base.calc_time(seeked.required_ts.saturating_sub(seeked.actual_ts));
+                skip = time.seconds as f64 + time.frac;
+            }
+        }
+
+        Ok(Source {
+            format,
+            decoder,
+            track_id,
+            skip,
+            length,
+            frames_left: None,
+        })
+    }
+
+    // Next block of interleaved 16-bit samples with its sample rate and channel count,
+    // corrupt packets are skipped.
+    pub fn next_block(&mut self) -> Result<Option<(Vec<i16>, u32, usize)>, TranscodeError> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(err))
+                    if err.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    return Ok(None);
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let (mut samples, rate, channels) = match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let spec = *decoded.spec();
+                    let mut samples = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+                    samples.copy_interleaved_ref(decoded);
+                    (
+                        samples.samples().to_vec(),
+                        spec.rate,
+                        spec.channels.count().max(1),
+                    )
+                }
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            if self.skip > 0.0 {
+                let frames = (self.skip * rate as f64).round() as usize;
+                let skipped = frames.min(samples.len() / channels);
+                samples.drain(..skipped * channels);
+                self.skip = (frames - skipped) as f64 / rate as f64;
+            }
+            if let Some(length) = self.length.take() {
+                self.frames_left = Some((length * rate as f64).round() as u64);
+            }
+            if let Some(frames_left) = self.frames_left {
+                if frames_left == 0 {
+                    return Ok(None);
+                }
+                let frames = (samples.len() / channels).min(frames_left as usize);
+                samples.truncate(frames * channels);
+                self.frames_left = Some(frames_left - frames as u64);
+            }
+            if !samples.is_empty() {
+                return Ok(Some((samples, rate, channels)));
+            }
+        }
+    }
+}
+
+// Where transcoded bytes go, told about the outcome once transcoding ended.
+pub trait Output: Write + Send + 'static {
+    fn finish(self, result: Result<(), TranscodeError>);
+}
+
+impl Output for ChannelWriter {
+    fn finish(self, result: Result<(), TranscodeError>) {
+        if let Err(err) = result {
+            eprintln!("Failed to transcode: {err:?}");
+        }
+    }
+}
+
+// Decodes the file with the built-in decoders and encodes it with the
+// configured encoder command, WAV is written without an external encoder.
+pub fn transcode(
+    settings: &TranscodingSettings,
+    path: &std::path::Path,
+    profile: Profile,
+    position: Position,
+) -> Result<TranscodeBody, TranscodeError> {
+    let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    transcode_into(settings, path, profile, position, ChannelWriter { sender })?;
+    Ok(TranscodeBody {
+        prefix: None,
+        receiver,
+    })
+}
+
+// Opening the input happens right away so that unsupported files fail
+// the request, decoding and encoding continue on a separate thread.
+pub fn transcode_into<O: Output>(
+    settings: &TranscodingSettings,
+    path: &std::path::Path,
+    profile: Profile,
+    position: Position,
+    mut output: O,
+) -> Result<(), TranscodeError> {
+    let mut source = Source::open(path, position)?;
+    let encoder = match profile.format {
+        Format::Wav => None,
+        format => Some(
+            settings
+                .encoders
+                .get(format.name())
+                .filter(|command| !command.is_empty())
+                .cloned()
+                .ok_or(TranscodeError::NoEncoder(format))?,
+        ),
+    };
+
+    std::thread::spawn(move || {
+        let result = run(&mut source, encoder, profile, &mut output);
+        output.finish(result);
+    });
+
+    Ok(())
+}
+
+fn run<O: Output>(
+    source: &mut Source,
+    encoder: Option<Vec<String>>,
+    profile: Profile,
+    output: &mut O,
+) -> Result<(), TranscodeError> {
+    let Some((samples, sample_rate, channels)) = source.next_block()? else {
+        return Ok(());
+    };
+
+    let Some(command) = encoder else {
+        output.write_all(&wav_header(sample_rate, channels))?;
+        let mut block = Some(samples);
+        while let Some(samples) = block {
+            output.write_all(&pcm_bytes(&samples))?;
+            block = source.next_block()?.map(|(samples, _, _)| samples);
+        }
+        return Ok(());
+    };
+
+    let args: Vec<String> = command
+        .iter()
+        .map(|arg| {
+            arg.replace("{bitrate}", &profile.bitrate.to_string())
+                .replace("{sample_rate}", &sample_rate.to_string())
+                .replace("{channels}", &channels.to_string())
+        })
+        .collect();
+    let mut child = std::process::Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take();
+    let stdout = child.stdout.take();
+    let cancelled = std::sync::atomic::AtomicBool::new(false);
+
+    let (written, forwarded) = std::thread::scope(|scope| {
+        // Keeps draining the encoder after the output failed so it never blocks on a full pipe.
+        let reader = scope.spawn(|| -> std::io::Result<()> {
+            let Some(mut stdout) = stdout else {
+                return Ok(());
+            };
+            let forwarded = std::io::copy(&mut stdout, output).map(|_| ());
+            if forwarded.is_err() {
+                cancelled.store(true, Ordering::Relaxed);
+                std::io::copy(&mut stdout, &mut std::io::sink())?;
+            }
+            forwarded
+        });
+
+        let written = (|| -> Result<(), TranscodeError> {
+            let stdin = stdin
+                .as_mut()
+                .ok_or(TranscodeError::NoEncoder(profile.format))?;
+            let mut block = Some(samples);
+            while let Some(samples) = block {
+                if cancelled.load(Ordering::Relaxed) {
+                    return Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe).into());
+                }
+                stdin.write_all(&pcm_bytes(&samples))?;
+                block = source.next_block()?.map(|(samples, _, _)| samples);
+            }
+            Ok(())
+        })();
+        drop(stdin);
+        if written.is_err() {
+            let _ = child.kill();
+        }
+
+        let forwarded = reader
+            .join()
+            .unwrap_or_else(|_| Err(std::io::Error::other("Encoder output thread panicked")));
+        (written, forwarded)
+    });
+
+    let status = child.wait()?;
+    written?;
+    forwarded?;
+    if !status.success() {
+        return Err(TranscodeError::EncoderFailed(status));
+    }
+    Ok(())
+}
+
+fn pcm_bytes(samples: &[i16]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect()
+}
+
+// The length is unknown while streaming, so the RIFF and data sizes are
+// left at their maximum, which players treat as "until the end of the stream".
+fn wav_header(sample_rate: u32, channels: usize) -> Vec<u8> {
+    let channels = channels as u16;
+    let block_align = channels * 2;
+    let mut header = vec![];
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&channels.to_le_bytes());
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    const SAMPLE_RATE: u32 = 8000;
+
+    // A WAV file of a 440 Hz sine wave, half a second of stereo 16-bit PCM.
+    fn sine_wav(dir: &TestDir, name: &str) -> (std::path::PathBuf, Vec<u8>) {
+        let samples: Vec<i16> = (0..SAMPLE_RATE / 2)
+            .flat_map(|i| {
+                let phase = i as f64 * 440.0 * std::f64::consts::TAU / SAMPLE_RATE as f64;
+                let sample = (phase.sin() * 8000.0) as i16;
+                [sample, sample]
+            })
+            .collect();
+        let pcm = pcm_bytes(&samples);
+        let mut data = wav_header(SAMPLE_RATE, 2);
+        data[4..8].copy_from_slice(&(36 + pcm.len() as u32).to_le_bytes());
+        data[40..44].copy_from_slice(&(pcm.len() as u32).to_le_bytes());
+        data.extend_from_slice(&pcm);
+
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        (path, pcm)
+    }
+
+    struct Collect {
+        data: Vec<u8>,
+        done: std::sync::mpsc::Sender<Result<Vec<u8>, TranscodeError>>,
+    }
+
+    impl Write for Collect {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Output for Collect {
+        fn finish(self, result: Result<(), TranscodeError>) {
+            let _ = self.done.send(result.map(|_| self.data));
+        }
+    }
+
+    fn transcoded(
+        settings: &TranscodingSettings,
+        path: &std::path::Path,
+        profile: Profile,
+        position: Position,
+    ) -> Result<Vec<u8>, TranscodeError> {
+        let (done, result) = std::sync::mpsc::channel();
+        let output = Collect { data: vec![], done };
+        transcode_into(settings, path, profile, position, output)?;
+        result.recv().unwrap()
+    }
+
+    fn with_encoder(format: &str, command: &[&str]) -> TranscodingSettings {
+        let mut settings = TranscodingSettings::default();
+        let command = command.iter().map(|arg| arg.to_string()).collect();
+        settings.encoders.insert(format.to_owned(), command);
+        settings
+    }
+
+    #[test]
+    fn default_format_without_encoder_serves_the_original() {
+        let settings = TranscodingSettings::default();
+        let path = std::path::Path::new("track.flac");
+        assert!(matches!(
+            negotiate(&settings, path, Some(900), None, Some(128)),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn requested_format_without_encoder_fails() {
+        let settings = TranscodingSettings::default();
+        let path = std::path::Path::new("track.flac");
+        assert!(matches!(
+            negotiate(&settings, path, Some(900), Some("mp3"), None),
+            Err(TranscodeError::NoEncoder(Format::Mp3))
+        ));
+        assert!(matches!(
+            negotiate(&settings, path, Some(900), Some("ogg"), None),
+            Err(TranscodeError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn default_format_with_encoder_transcodes() {
+        let dir = TestDir::new("default-format");
+        let (path, _) = sine_wav(&dir, "track.wav");
+        let settings = with_encoder("mp3", &["lame"]);
+        let profile = negotiate(&settings, &path, Some(256), None, Some(128)).unwrap();
+        assert_eq!(
+            profile,
+            Some(Profile {
+                format: Format::Mp3,
+                bitrate: 128
+            })
+        );
+    }
+
+    #[test]
+    fn fitting_and_raw_files_are_served_as_they_are() {
+        let settings = with_encoder("mp3", &["lame"]);
+        let path = std::path::Path::new("track.mp3");
+        assert!(matches!(
+            negotiate(&settings, path, Some(128), None, Some(192)),
+            Ok(None)
+        ));
+        assert!(matches!(
+            negotiate(&settings, path, Some(128), Some("mp3"), Some(192)),
+            Ok(None)
+        ));
+        assert!(matches!(
+            negotiate(&settings, path, Some(320), Some("raw"), Some(128)),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn wav_needs_no_encoder() {
+        let dir = TestDir::new("wav-encoder");
+        let (path, _) = sine_wav(&dir, "track.wav");
+        let settings = TranscodingSettings::default();
+        assert!(matches!(
+            negotiate(&settings, &path, Some(256), Some("wav"), Some(128)),
+            Ok(Some(Profile {
+                format: Format::Wav,
+                ..
+            }))
+        ));
+    }
+
+    // An Ogg page holding one whole packet, with its CRC so that it can be probed.
+    fn ogg_page(flags: u8, sequence: u32, packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\0".to_vec();
+        page.push(flags);
+        page.extend_from_slice(&0u64.to_le_bytes());
+        page.extend_from_slice(&1u32.to_le_bytes());
+        page.extend_from_slice(&sequence.to_le_bytes());
+        page.extend_from_slice(&0u32.to_le_bytes());
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend_from_slice(packet);
+        let crc = page.iter().fold(0u32, |crc, byte| {
+            (0..8).fold(crc ^ ((*byte as u32) << 24), |crc, _| {
+                match crc & 0x8000_0000 {
+                    0 => crc << 1,
+                    _ => (crc << 1) ^ 0x04c1_1db7,
+                }
+            })
+        });
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    #[test]
+    fn undecodable_files_are_served_as_they_are() {
+        let dir = TestDir::new("opus");
+        let path = dir.join("track.opus");
+        let mut head = b"OpusHead\x01\x02".to_vec();
+        head.extend_from_slice(&312u16.to_le_bytes());
+        head.extend_from_slice(&48000u32.to_le_bytes());
+        head.extend_from_slice(&[0, 0, 0]);
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        let data = [
+            ogg_page(0x02, 0, &head),
+            ogg_page(0x00, 1, &tags),
+            ogg_page(0x04, 2, &[0xf8, 0xff, 0xfe]),
+        ]
+        .concat();
+        std::fs::write(&path, data).unwrap();
+        // The container is read, there is just no decoder for the codec.
+        assert!(matches!(
+            Source::open(&path, Position::Start),
+            Err(TranscodeError::DecodeError(SymphoniaError::Unsupported(_)))
+        ));
+        assert!(!decodable(&path));
+
+        let settings = with_encoder("mp3", &["lame"]);
+        assert!(matches!(
+            negotiate(&settings, &path, Some(160), None, Some(96)),
+            Ok(None)
+        ));
+        assert!(matches!(
+            negotiate(&settings, &path, Some(160), Some("mp3"), None),
+            Err(TranscodeError::Undecodable)
+        ));
+        assert!(matches!(
+            negotiate(&settings, &path, Some(160), Some("raw"), Some(96)),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn transcodes_to_wav_without_encoder() {
+        let dir = TestDir::new("to-wav");
+        let (path, pcm) = sine_wav(&dir, "to-wav.wav");
+        let profile = Profile {
+            format: Format::Wav,
+            bitrate: 128,
+        };
+        let settings = TranscodingSettings::default();
+        let output = transcoded(&settings, &path, profile, Position::Start).unwrap();
+        assert_eq!(output[..44], wav_header(SAMPLE_RATE, 2)[..]);
+        assert_eq!(output[44..], pcm[..]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pipes_pcm_through_the_encoder() {
+        let dir = TestDir::new("encoder");
+        let (path, pcm) = sine_wav(&dir, "encoder.wav");
+        let profile = Profile {
+            format: Format::Mp3,
+            bitrate: 128,
+        };
+        let settings = with_encoder("mp3", &["cat"]);
+        let output = transcoded(&settings, &path, profile, Position::Start).unwrap();
+        assert_eq!(output, pcm);
+
+        let settings = with_encoder("mp3", &["false"]);
+        assert!(matches!(
+            transcoded(&settings, &path, profile, Position::Start),
+            Err(TranscodeError::EncoderFailed(_)) | Err(TranscodeError::IOError(_))
+        ));
+    }
+
+    #[test]
+    fn starts_at_the_offset() {
+        let dir = TestDir::new("offset");
+        let (path, pcm) = sine_wav(&dir, "offset.wav");
+        let profile = Profile {
+            format: Format::Wav,
+            bitrate: 128,
+        };
+        let settings = TranscodingSettings::default();
+        let output = transcoded(&settings, &path, profile, Position::Offset(0.25)).unwrap();
+        let skipped = pcm.len() - (output.len() - 44);
+        let half = pcm.len() / 2;
+        assert!(skipped > 0 && skipped <= half, "skipped {skipped} bytes");
+    }
+}
@@ -0,0 +1,394 @@
+use crate::AppState;
+use crate::conditional::{IMMUTABLE, Validators};
+use crate::range::{self, CHUNK_SIZE, RangeRequest, read_file_chunk};
+use crate::transcode::{Output, Position, Profile, TranscodeError, transcode, transcode_into};
+use actix_web::http::StatusCode;
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Running,
+    Finished,
+    Failed,
+}
+
+#[derive(Clone, Copy)]
+struct Progress {
+    written: u64,
+    state: State,
+}
+
+struct Entry {
+    size: u64,
+    last_used: std::time::SystemTime,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, Entry>,
+    pending: HashMap<String, watch::Receiver<Progress>>,
+    total: u64,
+}
+
+// Transcoded files stored as `<md5>-<bitrate>.<format>` (HLS segments as
+// `<md5>-<bitrate>-hls<index>.<format>`), evicted least recently
+// used first once they exceed the size limit. Entries are written to a
+// temporary file that is renamed when complete, other requests can read it
+// while it is still growing.
+#[derive(Clone)]
+pub struct TranscodeCache {
+    dir: std::path::PathBuf,
+    max_size: u64,
+    state: Arc<Mutex<CacheState>>,
+}
+
+enum Lookup {
+    Ready(std::path::PathBuf),
+    Pending(std::fs::File, watch::Receiver<Progress>),
+    Created(std::fs::File, watch::Receiver<Progress>, CacheWriter),
+}
+
+impl TranscodeCache {
+    // Picks up the entries of previous runs, their modification dates
+    // stand in for the last access, unfinished files are removed.
+    pub fn new(dir: std::path::PathBuf, max_size: u64) -> TranscodeCache {
+        let mut state = CacheState::default();
+        for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if name.starts_with('.') || max_size == 0 {
+                let _ = std::fs::remove_file(entry.path());
+                continue;
+            }
+            state.total += metadata.len();
+            state.entries.insert(
+                name,
+                Entry {
+                    size: metadata.len(),
+                    last_used: metadata.modified().unwrap_or(std::time::UNIX_EPOCH),
+                },
+            );
+        }
+
+        let cache = TranscodeCache {
+            dir,
+            max_size,
+            state: Arc::new(Mutex::new(state)),
+        };
+        if let Ok(mut state) = cache.state.lock() {
+            cache.evict(&mut state, None);
+        }
+        cache
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_size > 0
+    }
+
+    pub fn key(hash: &str, profile: Profile) -> String {
+        format!("{hash}-{}.{}", profile.bitrate, profile.format.name())
+    }
+
+    fn tmp_path(&self, key: &str) -> std::path::PathBuf {
+        self.dir.join(format!(".{key}.tmp"))
+    }
+
+    // Serves the cached entry `key`, calling `start` to produce it into
+    // the cache when nobody has done so yet.
+    pub async fn serve(
+        &self,
+        req: &HttpRequest,
+        key: &str,
+        mime: &str,
+        start: impl FnOnce(CacheWriter) -> Result<(), TranscodeError>,
+    ) -> Result<HttpResponse, TranscodeError> {
+        let (file, progress) = match self.lookup(key)? {
+            Lookup::Ready(path) => return self.serve_file(req, key, &path, mime),
+            Lookup::Pending(file, progress) => (file, progress),
+            Lookup::Created(file, progress, writer) => {
+                start(writer)?;
+                (file, progress)
+            }
+        };
+        self.serve_pending(req, key, file, progress, mime).await
+    }
+
+    fn lookup(&self, key: &str) -> Result<Lookup, TranscodeError> {
+        let mut state = self.lock()?;
+        if let Some(entry) = state.entries.get_mut(key) {
+            let path = self.dir.join(key);
+            if path.is_file() {
+                entry.last_used = std::time::SystemTime::now();
+                if let Ok(file) = std::fs::File::options().append(true).open(&path) {
+                    let _ = file.set_modified(entry.last_used);
+                }
+                return Ok(Lookup::Ready(path));
+            }
+            if let Some(entry) = state.entries.remove(key) {
+                state.total -= entry.size;
+            }
+        }
+        if let Some(progress) = state.pending.get(key) {
+            let file = std::fs::File::open(self.tmp_path(key))?;
+            return Ok(Lookup::Pending(file, progress.clone()));
+        }
+
+        std::fs::create_dir_all(&self.dir)?;
+        let tmp_path = self.tmp_path(key);
+        let file = std::fs::File::create(&tmp_path)?;
+        let reader = std::fs::File::open(&tmp_path)?;
+        let (sender, receiver) = watch::channel(Progress {
+            written: 0,
+            state: State::Running,
+        });
+        state.pending.insert(key.to_owned(), receiver.clone());
+        let writer = CacheWriter {
+            cache: self.clone(),
+            key: key.to_owned(),
+            file,
+            written: 0,
+            progress: sender,
+            finished: false,
+        };
+        Ok(Lookup::Created(reader, receiver, writer))
+    }
+
+    fn serve_file(
+        &self,
+        req: &HttpRequest,
+        key: &str,
+        path: &std::path::Path,
+        mime: &str,
+    ) -> Result<HttpResponse, TranscodeError> {
+        let validators = Validators::new(key, None, IMMUTABLE);
+        Ok(range::respond(
+            req,
+            HttpResponse::Ok(),
+            range::Source::new(path),
+            mime,
+            &validators,
+        )?)
+    }
+
+    // Without a range the growing file is streamed until it is complete.
+    // A range is answered once its bytes exist, ranges that need the total
+    // length wait for the entry to be finished.
+    async fn serve_pending(
+        &self,
+        req: &HttpRequest,
+        key: &str,
+        file: std::fs::File,
+        mut progress: watch::Receiver<Progress>,
+        mime: &str,
+    ) -> Result<HttpResponse, TranscodeError> {
+        let Some(requested) = range::requested(req) else {
+            return Ok(HttpResponse::Ok()
+                .content_type(mime)
+                .insert_header((header::ACCEPT_RANGES, "bytes"))
+                .streaming(growing_body(file, progress)));
+        };
+
+        let (first, last) = match requested {
+            RangeRequest::Bounded(first, last) => (first, Some(last)),
+            RangeRequest::From(first) => (first, None),
+            RangeRequest::Other => (u64::MAX, None),
+        };
+        let status = wait_for(&mut progress, last.unwrap_or(first).saturating_add(1)).await;
+        match status.state {
+            State::Failed => Err(std::io::Error::other("Transcoding failed").into()),
+            State::Finished => self.serve_file(req, key, &self.dir.join(key), mime),
+            State::Running => {
+                let end = last.map_or(status.written, |last| (last + 1).min(status.written));
+                let (_, body) = read_file_chunk(file, first, end - first).await?;
+                Ok(HttpResponse::build(StatusCode::PARTIAL_CONTENT)
+                    .content_type(mime)
+                    .insert_header((header::ACCEPT_RANGES, "bytes"))
+                    .insert_header((
+                        header::CONTENT_RANGE,
+                        format!("bytes {first}-{}/*", end - 1),
+                    ))
+                    .body(body))
+            }
+        }
+    }
+
+    fn commit(&self, key: &str, size: u64) -> std::io::Result<()> {
+        let mut state = self.lock().map_err(|_| std::io::Error::other("Poisoned"))?;
+        state.pending.remove(key);
+        std::fs::rename(self.tmp_path(key), self.dir.join(key))?;
+        state.total += size;
+        state.entries.insert(
+            key.to_owned(),
+            Entry {
+                size,
+                last_used: std::time::SystemTime::now(),
+            },
+        );
+        self.evict(&mut state, Some(key));
+        Ok(())
+    }
+
+    fn discard(&self, key: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.pending.remove(key);
+        }
+        let _ = std::fs::remove_file(self.tmp_path(key));
+    }
+
+    // The entry that was just added is kept even when it alone exceeds the limit,
+    // so the requests waiting for it can still be served.
+    fn evict(&self, state: &mut CacheState, keep: Option<&str>) {
+        while state.total > self.max_size {
+            let Some(key) = state
+                .entries
+                .iter()
+                .filter(|(key, _)| Some(key.as_str()) != keep)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(entry) = state.entries.remove(&key) {
+                state.total -= entry.size;
+            }
+            let _ = std::fs::remove_file(self.dir.join(&key));
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, CacheState>, TranscodeError> {
+        self.state
+            .lock()
+            .map_err(|_| std::io::Error::other("Poisoned transcode cache").into())
+    }
+}
+
+pub struct CacheWriter {
+    cache: TranscodeCache,
+    key: String,
+    file: std::fs::File,
+    written: u64,
+    progress: watch::Sender<Progress>,
+    finished: bool,
+}
+
+impl CacheWriter {
+    fn complete(&mut self, result: Result<(), TranscodeError>) {
+        self.finished = true;
+        let committed = result.and_then(|_| {
+            self.file.sync_all()?;
+            Ok(self.cache.commit(&self.key, self.written)?)
+        });
+        let state = match committed {
+            Ok(()) => State::Finished,
+            Err(err) => {
+                eprintln!("Failed to transcode {}: {err:?}", self.key);
+                self.cache.discard(&self.key);
+                State::Failed
+            }
+        };
+        self.progress.send_modify(|progress| progress.state = state);
+    }
+}
+
+impl Write for CacheWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        let total = self.written;
+        self.progress
+            .send_modify(|progress| progress.written = total);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Output for CacheWriter {
+    fn finish(mut self, result: Result<(), TranscodeError>) {
+        self.complete(result);
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.complete(Err(std::io::Error::other("Transcoding stopped").into()));
+        }
+    }
+}
+
+// Transcodes through the cache when it is enabled, seeked streams bypass it.
+pub async fn transcoded_response(
+    data: &AppState,
+    req: &HttpRequest,
+    hash: &str,
+    path: &std::path::Path,
+    profile: Profile,
+    time_offset: Option<f64>,
+) -> Result<HttpResponse, TranscodeError> {
+    let transcoding = &data.settings.transcoding;
+    if data.transcode_cache.enabled() && time_offset.is_none_or(|offset| offset <= 0.0) {
+        return data
+            .transcode_cache
+            .serve(
+                req,
+                &TranscodeCache::key(hash, profile),
+                profile.format.mime(),
+                |writer| transcode_into(transcoding, path, profile, Position::Start, writer),
+            )
+            .await;
+    }
+    let body = transcode(
+        transcoding,
+        path,
+        profile,
+        Position::from_offset(time_offset),
+    )?;
+    Ok(HttpResponse::Ok()
+        .content_type(profile.format.mime())
+        .insert_header((header::ACCEPT_RANGES, "none"))
+        .body(body))
+}
+
+async fn wait_for(progress: &mut watch::Receiver<Progress>, bytes: u64) -> Progress {
+    loop {
+        let status = *progress.borrow_and_update();
+        if status.state != State::Running || status.written >= bytes {
+            return status;
+        }
+        if progress.changed().await.is_err() {
+            return *progress.borrow();
+        }
+    }
+}
+
+fn growing_body(
+    file: std::fs::File,
+    progress: watch::Receiver<Progress>,
+) -> impl futures_util::Stream<Item = Result<Bytes, std::io::Error>> {
+    futures_util::stream::unfold(Some((file, 0, progress)), |state| async move {
+        let (file, position, mut progress) = state?;
+        let status = wait_for(&mut progress, position + 1).await;
+        if position < status.written {
+            let len = (status.written - position).min(CHUNK_SIZE);
+            return Some(match read_file_chunk(file, position, len).await {
+                Ok((file, chunk)) => (Ok(chunk), Some((file, position + len, progress))),
+                Err(error) => (Err(error), None),
+            });
+        }
+        match status.state {
+            State::Failed => Some((Err(std::io::Error::other("Transcoding failed")), None)),
+            _ => None,
+        }
+    })
+}
@@ -0,0 +1,160 @@
+use crate::scan::{ScanProgress, finish_scanning, poisoned, request_rescan, update_library};
+use crate::{AppState, TraverseError, is_audiofile, reuse_moved, update_files, walk_dir};
+use actix_web::web;
+use notify::event::{AccessKind, AccessMode};
+use notify::{Config, Event, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+// Used when inotify cannot watch the base directory and no interval is configured.
+const DEFAULT_POLL_INTERVAL: u64 = 60;
+
+// Watches the base directory with inotify, or by polling when configured or
+// when inotify is not available, and applies changes once events stop
+// arriving for the debounce time.
+pub fn start_watcher(data: web::Data<AppState>) {
+    let settings = &data.settings.watch;
+    if !settings.enabled {
+        return;
+    }
+    let base_dir = std::path::PathBuf::from(&data.base_dir);
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    let mut watcher: Option<Box<dyn Watcher + Send>> = None;
+    if settings.poll_interval.is_none() {
+        match RecommendedWatcher::new(sender.clone(), Config::default()).and_then(|mut watcher| {
+            watcher
+                .watch(&base_dir, RecursiveMode::Recursive)
+                .map(|_| watcher)
+        }) {
+            Ok(inotify) => watcher = Some(Box::new(inotify)),
+            Err(err) => eprintln!("Failed to watch {base_dir:?}, polling instead: {err:?}"),
+        }
+    }
+    if watcher.is_none() {
+        let interval = settings.poll_interval.unwrap_or(DEFAULT_POLL_INTERVAL);
+        let config = Config::default().with_poll_interval(Duration::from_secs(interval));
+        match PollWatcher::new(sender, config).and_then(|mut watcher| {
+            watcher
+                .watch(&base_dir, RecursiveMode::Recursive)
+                .map(|_| watcher)
+        }) {
+            Ok(poll) => watcher = Some(Box::new(poll)),
+            Err(err) => {
+                eprintln!("Failed to poll {base_dir:?}: {err:?}");
+                return;
+            }
+        }
+    }
+
+    let debounce = Duration::from_millis(settings.debounce);
+    std::thread::spawn(move || {
+        let _watcher = watcher;
+        watch(&data, receiver, debounce);
+    });
+}
+
+fn watch(
+    data: &web::Data<AppState>,
+    receiver: Receiver<notify::Result<Event>>,
+    debounce: Duration,
+) {
+    let mut pending: HashSet<std::path::PathBuf> = HashSet::new();
+    loop {
+        let event = match pending.is_empty() {
+            true => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            false => receiver.recv_timeout(debounce),
+        };
+        match event {
+            Ok(Ok(event)) => {
+                // Lost events leave nothing to go by but a full scan.
+                if event.need_rescan() {
+                    request_rescan(data.clone());
+                }
+                if relevant(&event.kind) {
+                    pending.extend(event.paths);
+                }
+            }
+            Ok(Err(err)) => eprintln!("Watch error: {err:?}"),
+            Err(RecvTimeoutError::Timeout) => {
+                if apply(data, &pending) {
+                    pending.clear();
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+// Opening and reading files, which streaming and hashing do all the time, changes nothing.
+fn relevant(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        EventKind::Access(_) => false,
+        _ => true,
+    }
+}
+
+// Changes wait while a scan runs, which returns false to keep them pending.
+fn apply(data: &web::Data<AppState>, paths: &HashSet<std::path::PathBuf>) -> bool {
+    if data
+        .scan_progress
+        .scanning
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        return false;
+    }
+    match update(data, paths) {
+        Ok(true) => crate::analysis::start_analysis(data.clone()),
+        Ok(false) => {}
+        Err(err) => eprintln!("Failed to apply file changes: {err:?}"),
+    }
+    finish_scanning(data);
+    true
+}
+
+// Paths that are gone take the entries below them along and directories are
+// walked again, so moved files show up as gone in one place and new in another.
+// Returns whether the library changed.
+fn update(data: &AppState, paths: &HashSet<std::path::PathBuf>) -> Result<bool, TraverseError> {
+    let mut cache = data.hashing_cache.lock().map_err(poisoned)?.clone();
+    let entries = cache.len();
+    let progress = ScanProgress::default();
+
+    let mut gone = vec![];
+    let mut touched = vec![];
+    for path in paths {
+        match std::fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => match walk_dir(path, &progress) {
+                Ok(files) => {
+                    let found: HashSet<&std::path::PathBuf> = files.iter().collect();
+                    gone.extend(cache.extract_if(|cached, _| {
+                        cached.starts_with(path) && !found.contains(cached)
+                    }));
+                    touched.extend(files);
+                }
+                Err(_) => gone.extend(cache.extract_if(|cached, _| cached.starts_with(path))),
+            },
+            Ok(metadata) => {
+                if metadata.is_file() && is_audiofile(path.clone()) {
+                    touched.push(path.clone());
+                }
+            }
+            Err(_) => gone.extend(cache.extract_if(|cached, _| cached.starts_with(path))),
+        }
+    }
+    touched.sort();
+    touched.dedup();
+    let moved_or_removed = !gone.is_empty();
+    reuse_moved(&mut cache, gone, &touched);
+
+    let hashed = update_files(&mut cache, touched, data.settings.library.ids, &progress)?;
+    if !moved_or_removed && hashed == 0 && cache.len() == entries {
+        return Ok(false);
+    }
+    update_library(data, cache, &progress)?;
+    Ok(true)
+}